@@ -0,0 +1,29 @@
+//! Compare the layered layout of the concept map with the random
+//! swapping we used to do, in both time and crossings, on
+//! progression.yaml.  Run with `cargo bench --bench layout`.
+
+use progression_website::data::Data;
+use std::time::Instant;
+
+fn main() {
+    let data = Data::new();
+    println!("{:>5} {:>22} {:>22}", "width", "random swaps", "layered");
+    for &width in &[2, 4, 8, 16] {
+        let start = Instant::now();
+        let annealed = data.annealed_concept_map(width, 1 << 18);
+        let annealed_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut layered = data.layered_concept_map(width);
+        let runs = 10;
+        for _ in 1..runs {
+            layered = data.layered_concept_map(width);
+        }
+        let layered_time = start.elapsed()/runs;
+
+        println!("{:>5} {:>8.3}s {:>4} crossings {:>8.3}s {:>4} crossings",
+                 width,
+                 annealed_time.as_secs_f64(), annealed.num_crossings(),
+                 layered_time.as_secs_f64(), layered.num_crossings());
+    }
+}
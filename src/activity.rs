@@ -6,7 +6,7 @@ use crate::data::{absolute_url, Course,
                   ConceptRepresentationID, ConceptRepresentationView,
                   ConceptRepresentationChoice,
                   ActivityID,
-                  PrereqCourse, ChangeRelationship};
+                  PrereqCourse, ChangeRelationship, MentionedIn};
 use crate::markdown::Markdown;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -45,6 +45,7 @@ pub struct ActivityView {
     pub courses: Vec<Course>,
     pub figure: Option<String>,
     pub long_description: Markdown,
+    pub mentioned_in: MentionedIn,
     pub external_url: Option<String>,
 }
 
@@ -0,0 +1,23 @@
+use progression_website::data::{ set_base_url, set_relative_mirror, Data };
+use progression_website::mathml::set_mirror_mathml;
+use progression_website::mirror;
+use clapme::ClapMe;
+
+#[derive(Debug, ClapMe)]
+struct Args {
+    base_url: String,
+    /// Render math as MathML rather than with MathJax
+    mathml: bool,
+    /// Link with relative urls, so the mirror works from anywhere
+    relative: bool,
+    /// Rebuild every page, even those that look up to date
+    all: bool,
+}
+
+fn main() {
+    let args = Args::from_args();
+    set_base_url(&args.base_url);
+    set_mirror_mathml(args.mathml);
+    set_relative_mirror(args.relative);
+    mirror::build(&Data::new(), args.all);
+}
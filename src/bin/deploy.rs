@@ -0,0 +1,31 @@
+use progression_website::deploy::{deploy, rollback};
+use clapme::ClapMe;
+use std::path::Path;
+
+#[derive(Debug, ClapMe)]
+struct Args {
+    /// The directory the site is served from
+    target: String,
+    /// Put back what was there before the last deploy
+    rollback: bool,
+    /// Where to keep what we need to roll back (.deploy by default)
+    state: Option<String>,
+}
+
+fn main() {
+    let args = Args::from_args();
+    let target = Path::new(&args.target);
+    let state = Path::new(args.state.as_deref().unwrap_or(".deploy"));
+    let result = if args.rollback {
+        rollback(target, state)
+    } else {
+        deploy(Path::new("mirror"), target, state)
+    };
+    match result {
+        Ok(d) => println!("Copied {} files and removed {}.", d.copied, d.removed),
+        Err(e) => {
+            eprintln!("Error deploying: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use progression_website::data::Data;
+use clapme::ClapMe;
+
+#[derive(Debug, ClapMe)]
+struct Args {
+    /// The format to write: graphml, json or mermaid
+    format: String,
+}
+
+fn main() {
+    let args = Args::from_args();
+    let graph = Data::new().concept_graph();
+    match args.format.as_str() {
+        "graphml" => print!("{}", graph.graphml()),
+        "json" => println!("{}", graph.json_graph()),
+        "mermaid" | "mmd" => print!("{}", graph.mermaid()),
+        f => {
+            eprintln!("Unknown format {:?}: try graphml, json or mermaid", f);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,26 +1,36 @@
 use warp::{Reply, Filter, path};
-use progression_website::data::{ set_base_url, Data, Change, CourseID, AnyID,
-                                 ConceptID, ActivityID, RepresentationID };
+use progression_website::data::{ set_base_url, set_relative_mirror, Data, Change,
+                                 CourseID, AnyID, ConceptID, ActivityID, RepresentationID,
+                                 DotOptions };
+use progression_website::mathml::set_mirror_mathml;
+use progression_website::mirror::spawn_build;
 use display_as::{HTML, display};
 use clapme::ClapMe;
 
 #[derive(Debug, ClapMe)]
 struct Args {
     base_url: String,
+    /// Render math in the mirror as MathML rather than with MathJax
+    mathml: bool,
+    /// Link with relative urls in the mirror, so it works from anywhere
+    relative: bool,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::from_args();
     set_base_url(&args.base_url);
+    set_mirror_mathml(args.mathml);
+    set_relative_mirror(args.relative);
 
-    Data::new().spawn_dump_mirror();
+    spawn_build();
     let change = path!("change")
         .and(warp::filters::body::form())
         .map(|change: Change| {
             if let Err(e) = Data::new().change(change.clone()) {
                 println!("Error {} while changing {:?}", e, change);
             }
+            spawn_build();
             "okay"
         });
     let figure = path!("figure" / AnyID / String)
@@ -32,6 +42,7 @@ async fn main() {
             if let Err(e) = Data::new().uploaded_figure(id, &filename) {
                 println!("Error {} while coping with new file {:?}", e, filename);
             }
+            spawn_build();
             "okay"
         });
     let concept = path!("concept" / ConceptID)
@@ -40,51 +51,97 @@ async fn main() {
             display(HTML, &data.concept_view(id))
                 .into_response()
         });
+    let neighborhood = path!("concept" / ConceptID / "map")
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .map(|id: ConceptID, query: std::collections::HashMap<String, String>| {
+            let data = Data::new();
+            let depth = match query.get("depth").map(|d| d.parse()) {
+                None => 2,
+                Some(Ok(depth)) => depth,
+                Some(Err(_)) => return warp::http::StatusCode::BAD_REQUEST.into_response(),
+            };
+            if data.has_concept(id) {
+                display(HTML, &data.neighborhood_map(id, depth, 4, false)).into_response()
+            } else {
+                warp::http::StatusCode::NOT_FOUND.into_response()
+            }
+        });
     let course = path!("course" / CourseID)
         .map(|id: CourseID| {
             let data = Data::new();
-            display(HTML, &data.course_view(id)).into_response()
+            if data.has_course(id) {
+                display(HTML, &data.course_view(id)).into_response()
+            } else {
+                warp::http::StatusCode::NOT_FOUND.into_response()
+            }
+        });
+    let courses = path!("courses")
+        .map(|| {
+            display(HTML, &Data::new().course_list()).into_response()
+        });
+    let course_order = path!("course" / CourseID / "order")
+        .map(|id: CourseID| {
+            let data = Data::new();
+            if data.has_course(id) {
+                display(HTML, &data.course_order(id)).into_response()
+            } else {
+                warp::http::StatusCode::NOT_FOUND.into_response()
+            }
         });
-    let map = path!("concept-map" / usize)
-        .map(|max_width: usize| {
+    let course_map = path!("concept-map" / "course" / CourseID)
+        .map(|id: CourseID| {
             let data = Data::new();
-            display(HTML, &data.concept_map(max_width, 1 << 18)).into_response()
+            if data.has_course(id) {
+                display(HTML, &data.course_map(id, 4, false)).into_response()
+            } else {
+                warp::http::StatusCode::NOT_FOUND.into_response()
+            }
+        });
+    let representation_map = path!("concept-map" / "representations" / RepresentationID)
+        .map(|id: RepresentationID| {
+            display(HTML, &Data::new().representation_map(Some(id), None, 4, false)).into_response()
         })
-        .or(path!("concept-map")
-            .map(|| {
+        .or(path!("concept-map" / "representations")
+            .and(warp::query::<std::collections::HashMap<String, String>>())
+            .map(|query: std::collections::HashMap<String, String>| {
                 let data = Data::new();
-                display(HTML, &data.concept_map(4, 1 << 18)).into_response()
+                let expand = query.get("expand").map(|e| data.expansion(e));
+                display(HTML, &data.representation_map(None, expand.as_ref(), 4, false)).into_response()
             }));
-    let dot = path!("concept-map.dot")
-        .map(|| {
+    let map = path!("concept-map" / usize).map(Some)
+        .or(path!("concept-map").map(|| None)).unify()
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .map(|max_width: Option<usize>, query: std::collections::HashMap<String, String>| {
+            let hide_implied = query.get("implied").map(|i| i.as_str()) == Some("hide");
             let data = Data::new();
-            let mut out: Vec<u8> = Vec::new();
-            dot::render(&data, &mut out).expect("Trouble rendering dot!");
-            String::from_utf8(out).expect("trouble converting utf8?")
+            display(HTML, &data.latest_concept_map(max_width.unwrap_or(4), hide_implied))
+                .into_response()
         });
-    let dotsvg = path!("concept-map.svg")
+    let dot = path!("concept-map.dot")
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .map(|query: std::collections::HashMap<String, String>| {
+            Data::new().concept_dot(&DotOptions::from_query(&query))
+        });
+    let svg = path!("concept-map.svg")
         .map(|| {
-            let data = Data::new();
-            let mut child = std::process::Command::new("dot")
-                .args(&["-Tsvg"])
-                .stdin(std::process::Stdio::piped())
-                .stdout(std::process::Stdio::piped())
-                .spawn()
-                .expect("failed to spawn dot");
-            {
-                use std::io::Write;
-                let stdin = child.stdin.as_mut().expect("Failed to open stdin");
-                stdin.write(b"digraph concept_map { ratio=compress; size=\"8,1000\"\n").expect("trouble writing to pipe");
-                let mut out: Vec<u8> = Vec::new();
-                dot::render(&data, &mut out).expect("Trouble rendering dot!");
-                // 22 is the number of bytes in the first line of the
-                // generated code.  This is way hokey.
-                stdin.write(&out[22..]).expect("trouble foo");
-                //println!("file: {}", String::from_utf8(Vec::from(&out[22..])).unwrap());
-            }
-            let output = child.wait_with_output().expect("Failed to read stdout");
-            String::from_utf8(output.stdout).expect("Trouble utf8")
+            let svg = Data::new().concept_map_svg(4);
+            warp::reply::with_header(svg, "content-type", "image/svg+xml").into_response()
         });
+    let graph = path!("concept-graph.graphml")
+        .map(|| {
+            warp::reply::with_header(Data::new().concept_graph().graphml(),
+                                     "content-type", "application/graphml+xml")
+        })
+        .or(path!("concept-graph.json")
+            .map(|| {
+                warp::reply::with_header(Data::new().concept_graph().json_graph(),
+                                         "content-type", "application/json")
+            }))
+        .or(path!("concept-graph.mmd")
+            .map(|| {
+                warp::reply::with_header(Data::new().concept_graph().mermaid(),
+                                         "content-type", "text/plain; charset=utf-8")
+            }));
     let representation = path!("representation" / RepresentationID)
         .map(|id: RepresentationID| {
             let data = Data::new();
@@ -100,6 +157,51 @@ async fn main() {
             let data = Data::new();
             display(HTML, &data.activity_view(id)).into_response()
         });
+    let api = path!("api" / "concept" / ConceptID).map(AnyID::Concept)
+        .or(path!("api" / "activity" / ActivityID).map(AnyID::Activity)).unify()
+        .or(path!("api" / "representation" / RepresentationID).map(AnyID::Representation)).unify()
+        .map(|id: AnyID| {
+            if let Some(json) = Data::new().json(id) {
+                warp::reply::with_header(json, "content-type", "application/json")
+                    .into_response()
+            } else {
+                warp::http::StatusCode::NOT_FOUND.into_response()
+            }
+        });
+    let learning_path = path!("path")
+        .and(warp::query::<Vec<(String, String)>>())
+        .map(|query: Vec<(String, String)>| {
+            display(HTML, &Data::new().path_page(&query)).into_response()
+        });
+    let api_path = path!("api" / "path")
+        .and(warp::query::<Vec<(String, String)>>())
+        .map(|query: Vec<(String, String)>| {
+            let page = Data::new().path_page(&query);
+            match page.path {
+                Some(path) if page.unknown.is_empty() => {
+                    let json = serde_json::to_string_pretty(&path).expect("trouble generating json");
+                    warp::reply::with_header(json, "content-type", "application/json")
+                        .into_response()
+                }
+                _ => {
+                    let problem = if page.unknown.is_empty() {
+                        "Which concept do you want to reach?".to_string()
+                    } else {
+                        format!("Couldn't find: {}", page.unknown.join(", "))
+                    };
+                    warp::reply::with_status(problem, warp::http::StatusCode::BAD_REQUEST)
+                        .into_response()
+                }
+            }
+        });
+    let coverage = path!("coverage")
+        .map(|| {
+            display(HTML, &Data::new().coverage()).into_response()
+        });
+    let report = path!("report")
+        .map(|| {
+            display(HTML, &Data::new().report()).into_response()
+        });
     let index = (warp::path::end().or(path!("index.html")))
         .map(|_| {
             display(HTML, &Data::new().progression_view()).into_response()
@@ -109,15 +211,26 @@ async fn main() {
     let figs = path!("figs").and(warp::fs::dir("figs"));
 
     warp::serve(index
+                .or(course_map)
+                .or(course_order)
+                .or(representation_map)
+                .or(neighborhood)
                 .or(map)
                 .or(dot)
-                .or(dotsvg)
+                .or(svg)
+                .or(graph)
                 .or(change)
                 .or(concept)
                 .or(activity)
                 .or(course)
+                .or(courses)
                 .or(representation)
                 .or(representations)
+                .or(report)
+                .or(coverage)
+                .or(api)
+                .or(api_path)
+                .or(learning_path)
                 .or(libraries)
                 .or(figs)
                 .or(figure)
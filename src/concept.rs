@@ -7,7 +7,7 @@ use crate::data::{absolute_url, Course,
                   RepresentationID, Child, Representation, ConceptRepresentationID,
                   Activity, ActivityChoice, ActivityGroup, ConceptID,
                   ConceptChoice, AnyChoice, ChangeRelationship,
-                  PrereqCourse};
+                  PrereqCourse, MentionedIn, ImpliedPrereq};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Concept {
@@ -61,6 +61,9 @@ pub struct ConceptView {
     pub courses: Vec<Course>,
     pub figure: Option<String>,
     pub long_description: Markdown,
+    pub mentioned_in: MentionedIn,
+    /// Prerequisites that follow from the other prerequisites.
+    pub implied_prereqs: Vec<ImpliedPrereq>,
 }
 #[with_template("[%" "%]" "concept-view.html")]
 impl DisplayAs<HTML> for ConceptView {}
@@ -103,6 +106,7 @@ impl ConceptView {
             id: format_as!(HTML, self.id),
             field: "taught by".to_string(),
             choices: Vec::new(),
+            positions: Vec::new(),
         };
         for a in self.all_activities.iter() {
             // Try to list only the concepts that we might plausibly want.
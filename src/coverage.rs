@@ -0,0 +1,188 @@
+use display_as::{with_template, HTML, DisplayAs};
+use serde::{Serialize, Deserialize};
+use crate::atomicfile::AtomicFile;
+use crate::data::{absolute_url, Activity, Concept, ConceptRepresentationID, Data, Representation};
+
+/// Where we keep the coverage counts each time they change.
+const HISTORY: &str = "coverage-history.json";
+
+lazy_static::lazy_static! {
+    /// Held while updating the history, so two saves can't each
+    /// append to the same old history.
+    static ref RECORDING: std::sync::Mutex<()> = std::sync::Mutex::new(());
+}
+
+/// What the activities leave out.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// Concepts that no activity teaches in any representation.
+    pub untaught_concepts: Vec<Concept>,
+    /// Concept-representations that no activity teaches, with their
+    /// names.
+    pub untaught_concept_representations: Vec<(ConceptRepresentationID, String)>,
+    /// Activities with no new concepts.
+    pub idle_activities: Vec<Activity>,
+    /// Representations that no concept is in.
+    pub unused_representations: Vec<Representation>,
+    /// Concepts with no prerequisites that nothing needs.
+    pub isolated_concepts: Vec<Concept>,
+    /// The counts as they have been, oldest first.
+    pub history: Vec<CoverageRecord>,
+}
+#[with_template("[%" "%]" "coverage.html")]
+impl DisplayAs<HTML> for Coverage {}
+
+/// How long each list on the coverage page is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageCounts {
+    pub untaught_concepts: usize,
+    pub untaught_concept_representations: usize,
+    pub idle_activities: usize,
+    pub unused_representations: usize,
+    pub isolated_concepts: usize,
+}
+
+/// The coverage counts since some time, in seconds since 1970.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageRecord {
+    pub time: u64,
+    pub counts: CoverageCounts,
+}
+
+impl Coverage {
+    pub fn counts(&self) -> CoverageCounts {
+        CoverageCounts {
+            untaught_concepts: self.untaught_concepts.len(),
+            untaught_concept_representations: self.untaught_concept_representations.len(),
+            idle_activities: self.idle_activities.len(),
+            unused_representations: self.unused_representations.len(),
+            isolated_concepts: self.isolated_concepts.len(),
+        }
+    }
+}
+
+pub(crate) fn load_history() -> Vec<CoverageRecord> {
+    std::fs::read_to_string(HISTORY).ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// Remember the counts, unless they are the same as last time.
+pub(crate) fn record(counts: CoverageCounts) {
+    let _lock = RECORDING.lock().unwrap();
+    let mut history = load_history();
+    if history.last().map(|r| r.counts) == Some(counts) {
+        return;
+    }
+    let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0);
+    history.push(CoverageRecord { time, counts });
+    if let Ok(f) = AtomicFile::create(HISTORY) {
+        serde_json::to_writer_pretty(&f, &history).ok();
+    }
+}
+
+impl Data {
+    /// The concepts, and concepts in a representation, that no
+    /// activity teaches.  A concept counts as taught if any activity
+    /// teaches it in any representation.
+    fn untaught_concept_representations(&self) -> Vec<ConceptRepresentationID> {
+        let taught: std::collections::BTreeSet<ConceptRepresentationID> = self.activities.iter()
+            .flat_map(|a| a.new_concepts.iter().flat_map(|&cr| vec![cr, cr.concept.into()]))
+            .collect();
+        let mut ids: Vec<ConceptRepresentationID> = self.concepts.iter()
+            .map(|c| c.id.into())
+            .chain(self.concepts.iter()
+                   .flat_map(|c| {
+                       let cid = c.id;
+                       c.representations.keys()
+                           .map(move |&x| (cid, x).into())
+                   }))
+            .filter(|cr| !taught.contains(cr))
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+    /// What the activities leave out, and how that has changed since
+    /// each save.
+    pub fn coverage(&self) -> Coverage {
+        Coverage { history: crate::coverage::load_history(), ..self.current_coverage() }
+    }
+    pub(crate) fn current_coverage(&self) -> Coverage {
+        let untaught = self.untaught_concept_representations();
+        Coverage {
+            untaught_concepts: untaught.iter()
+                .filter(|cr| cr.representation.is_none())
+                .map(|cr| self.get(cr.concept).clone())
+                .collect(),
+            untaught_concept_representations: untaught.iter()
+                .filter(|cr| cr.representation.is_some())
+                .map(|&cr| (cr, self.name_it(cr)))
+                .collect(),
+            idle_activities: self.activities.iter()
+                .filter(|a| a.new_concepts.is_empty())
+                .cloned()
+                .collect(),
+            unused_representations: self.representations.iter()
+                .filter(|r| !self.concepts.iter().any(|c| c.representations.contains_key(&r.id)))
+                .cloned()
+                .collect(),
+            isolated_concepts: self.concepts.iter()
+                .filter(|c| c.prereq_concepts.is_empty()
+                        && !self.concepts.iter().any(|x| x.prereq_concepts.contains(&c.id)))
+                .cloned()
+                .collect(),
+            history: Vec::new(),
+        }
+    }
+}
+
+/// The date (in UTC) some number of seconds after 1970 began, as
+/// YYYY-MM-DD.
+pub fn date(time: u64) -> String {
+    // Count days from 1 March 2000, so leap days come at the end of
+    // each year and of each 400 year era.
+    let days = (time / 86400) as i64 - 11017;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096) / 365;
+    let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+    let month = (5*day_of_year + 2)/153;
+    let day = day_of_year - (153*month + 2)/5 + 1;
+    let year = 2000 + 400*era + year_of_era + if month >= 10 { 1 } else { 0 };
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[test]
+fn dates() {
+    assert_eq!(date(0), "1970-01-01");
+    assert_eq!(date(951782400), "2000-02-29");
+    assert_eq!(date(951868800), "2000-03-01");
+    assert_eq!(date(1700000000), "2023-11-14");
+    assert_eq!(date(4107542400), "2100-03-01");
+}
+
+#[test]
+fn coverage_lists_what_nothing_teaches() {
+    let data = Data::new();
+    let coverage = data.current_coverage();
+    let taught = |cr: ConceptRepresentationID| data.activities.iter().any(|a| a.new_concepts.contains(&cr));
+    for c in coverage.untaught_concepts.iter() {
+        assert!(data.activities.iter().all(|a| a.new_concepts.iter().all(|cr| cr.concept != c.id)));
+    }
+    for &(cr, _) in coverage.untaught_concept_representations.iter() {
+        assert!(cr.representation.is_some() && !taught(cr));
+    }
+    for c in data.concepts.iter() {
+        for &r in c.representations.keys() {
+            let cr = (c.id, r).into();
+            assert!(taught(cr) || coverage.untaught_concept_representations.iter().any(|&(x, _)| x == cr));
+        }
+    }
+    assert!(coverage.idle_activities.iter().all(|a| a.new_concepts.is_empty()));
+    for c in coverage.isolated_concepts.iter() {
+        assert!(!data.prereq_edges().iter().any(|&(a, b)| a == c.id || b == c.id));
+    }
+}
@@ -2,6 +2,8 @@ use crate::atomicfile::AtomicFile;
 use serde::{Serialize, Deserialize};
 use display_as::{with_template, format_as, HTML, UTF8, URL, DisplayAs};
 use crate::markdown::Markdown;
+use crate::sanitize::sanitize_html;
+use crate::layout::{Layout, Slot};
 
 lazy_static::lazy_static! {
     static ref BASE: std::sync::Mutex<String> = std::sync::Mutex::new(String::new());
@@ -12,20 +14,141 @@ pub fn set_base_url(new_base: &str) {
 fn base_url() -> String {
     BASE.lock().unwrap().clone()
 }
+static RELATIVE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+/// Whether the mirror should link with relative urls, so it works
+/// wherever it is put.
+pub fn set_relative_mirror(relative: bool) {
+    RELATIVE.store(relative, std::sync::atomic::Ordering::Relaxed);
+}
+pub fn relative_mirror() -> bool {
+    RELATIVE.load(std::sync::atomic::Ordering::Relaxed)
+}
+thread_local! {
+    static PAGE: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+/// Make `absolute_url` on this thread give urls relative to the
+/// mirror page at the given url, or stop doing so.
+pub fn set_current_page(url: Option<String>) {
+    PAGE.with(|p| *p.borrow_mut() = url);
+}
+thread_local! {
+    static CANONICAL: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+/// Tell the pages rendered on this thread which url they are the
+/// mirror's copy of, so they can link to their canonical url.
+pub fn set_canonical_page(url: Option<String>) {
+    CANONICAL.with(|p| *p.borrow_mut() = url);
+}
+/// The url of the map of the concepts near a concept, which (unlike
+/// `absolute_url`) may have a query.
+pub fn neighborhood_url(id: ConceptID, depth: Option<usize>) -> impl DisplayAs<HTML> {
+    let url = absolute(format!("/concept/{}/map", id.0));
+    match depth {
+        Some(depth) => AbsoluteURL(format!("{}?depth={}", url, depth)),
+        None => AbsoluteURL(url),
+    }
+}
+/// The url of the concept map, perhaps without the implied
+/// prerequisites.
+pub fn concept_map_url(max_width: usize, hide_implied: bool) -> impl DisplayAs<HTML> {
+    let url = absolute(format!("/concept-map/{}", max_width));
+    if hide_implied {
+        AbsoluteURL(format!("{}?implied=hide", url))
+    } else {
+        AbsoluteURL(url)
+    }
+}
+/// The url of the map by representation with only some concepts
+/// split into their concept-representations.
+pub fn representation_map_expanding(expand: &std::collections::BTreeSet<ConceptID>)
+                                    -> impl DisplayAs<HTML> {
+    let ids: Vec<String> = expand.iter().map(|&c| format_as!(HTML, c)).collect();
+    AbsoluteURL(format!("{}?expand={}", absolute(representation_map_url(None)), ids.join(",")))
+}
+/// The url of the learning path to a concept.
+pub fn learning_path_url(to: ConceptID) -> impl DisplayAs<HTML> {
+    AbsoluteURL(format!("{}?to={}", absolute("/path".to_string()), to.0))
+}
+/// Whether we are writing a page of the mirror, which has no pages
+/// that depend on a query.
+pub fn building_mirror() -> bool {
+    CANONICAL.with(|p| p.borrow().is_some())
+}
+/// The canonical url of the mirror page being written, if any.
+pub fn canonical_url() -> Option<impl DisplayAs<HTML>> {
+    CANONICAL.with(|p| p.borrow().clone()).map(|url| AbsoluteURL(published_url(&url)))
+}
+/// The url at which the mirror publishes the page with a given url,
+/// which is only a full url if the base url is.
+pub fn published_url(url: &str) -> String {
+    if relative_mirror() {
+        format!("{}/{}", base_url().trim_end_matches('/'), page_file(url))
+    } else {
+        format!("{}{}", base_url(), url)
+    }
+}
+/// The file (relative to the top of a relative mirror) that holds
+/// the page with a given url.  Pages get an extension, so that they
+/// can be viewed without a web server.
+pub fn page_file(url: &str) -> String {
+    let url = url.trim_start_matches('/');
+    let last = url.rsplit('/').next().unwrap_or("");
+    if last.is_empty() {
+        format!("{}index.html", url)
+    } else if last.contains('.') {
+        url.to_string()
+    } else {
+        format!("{}.html", url)
+    }
+}
+/// Whether a url starts with a scheme such as http:.
+fn has_scheme(url: &str) -> bool {
+    match url.find(':') {
+        Some(i) => i > 0 && url[..i].chars().all(|c| c.is_ascii_alphanumeric() || c == '+'
+                                                 || c == '-' || c == '.'),
+        None => false,
+    }
+}
+
 #[derive(Debug, Clone)]
 struct AbsoluteURL(String);
-#[with_template(r#"""# base_url() as UTF8 "" self.0 as UTF8 r#"""#)]
+#[with_template(r#"""# self.0 as UTF8 r#"""#)]
 impl DisplayAs<HTML> for AbsoluteURL {}
 
+/// The url of something on the site, which is relative to the
+/// current page when writing a relative mirror.
 pub fn absolute_url(u: impl DisplayAs<URL>) -> impl DisplayAs<HTML> {
-    let x = format_as!(URL, u);
-    if x.starts_with("/") {
-        AbsoluteURL(x)
+    AbsoluteURL(absolute(format_as!(URL, u)))
+}
+
+/// A url made absolute (or relative to the mirror page being
+/// written) as `absolute_url` does, as plain text.
+pub(crate) fn absolute(x: String) -> String {
+    if has_scheme(&x) {
+        return x;
+    }
+    let x = if x.starts_with('/') { x } else { format!("/{}", x) };
+    if let Some(page) = PAGE.with(|p| p.borrow().clone()) {
+        let depth = page.trim_start_matches('/').matches('/').count();
+        format!("{}{}", "../".repeat(depth), page_file(&x))
     } else {
-        AbsoluteURL(format!("/{}", x))
+        format!("{}{}", base_url(), x)
     }
 }
 
+#[test]
+fn relative_urls() {
+    set_current_page(Some("/concept/3".to_string()));
+    assert_eq!(format_as!(HTML, absolute_url(ConceptID(4))), r#""../concept/4.html""#);
+    assert_eq!(format_as!(HTML, absolute_url("")), r#""../index.html""#);
+    assert_eq!(format_as!(HTML, absolute_url("style.css")), r#""../style.css""#);
+    assert_eq!(format_as!(HTML, absolute_url("http://example.com/x")), r#""http://example.com/x""#);
+    set_current_page(Some("/".to_string()));
+    assert_eq!(format_as!(HTML, absolute_url("concept-map/2")), r#""concept-map/2.html""#);
+    set_current_page(None);
+    assert_eq!(format_as!(HTML, absolute_url("concept-map/2")), r#""/concept-map/2""#);
+}
+
 #[derive(Debug, Hash, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Change {
     pub id: String,
@@ -35,7 +158,7 @@ pub struct Change {
 }
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct ConceptID(usize);
+pub struct ConceptID(pub(crate) usize);
 #[with_template("c" self.0)]
 impl DisplayAs<HTML> for ConceptID {}
 #[with_template("/concept/" self.0)]
@@ -49,9 +172,9 @@ impl std::str::FromStr for ConceptID {
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct ConceptRepresentationID {
-    concept: ConceptID,
+    pub(crate) concept: ConceptID,
     #[serde(skip_serializing_if = "Option::is_none")]
-    representation: Option<RepresentationID>,
+    pub(crate) representation: Option<RepresentationID>,
 }
 #[with_template( if let Some(rid) = self.representation {
     self.concept "-" rid
@@ -125,7 +248,7 @@ impl From<(ConceptID, RepresentationID)> for ConceptRepresentationID {
 }
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct ActivityID(usize);
+pub struct ActivityID(pub(crate) usize);
 #[with_template("a" self.0)]
 impl DisplayAs<HTML> for ActivityID {}
 #[with_template("/activity/" self.0)]
@@ -138,7 +261,7 @@ impl std::str::FromStr for ActivityID {
 }
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct RepresentationID(usize);
+pub struct RepresentationID(pub(crate) usize);
 #[with_template("/representation/" self.0)]
 impl DisplayAs<URL> for RepresentationID {}
 #[with_template("r" self.0)]
@@ -150,8 +273,8 @@ impl std::str::FromStr for RepresentationID {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct CourseID(usize);
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CourseID(pub(crate) usize);
 #[with_template("C" self.0)]
 impl DisplayAs<HTML> for CourseID {}
 #[with_template("/course/" self.0)]
@@ -168,7 +291,16 @@ pub struct Course {
     pub id: CourseID,
     pub number: String,
     pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub term: String,
+    #[serde(default)]
+    pub long_description: Markdown,
     pub activities: Vec<ActivityID>,
+    /// A deleted course keeps its id, so that nothing else can take
+    /// it and old links stay unresolved rather than pointing
+    /// elsewhere.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
 }
 #[with_template( self.id )]
 impl DisplayAs<URL> for Course {}
@@ -179,16 +311,26 @@ pub use crate::concept::{Concept, ConceptView,
                          ConceptRepresentation, ConceptRepresentationView};
 pub use crate::activity::{Activity, ActivityView};
 pub use crate::representation::{Icon, Representation, RepresentationView};
+pub use crate::report::{Report, UnresolvedReference, ImpliedPrereq, PrereqWarning};
+pub use crate::path::{LearningPath, PathPage, PathStep};
+pub use crate::coverage::Coverage;
+pub use crate::mentions::{Mention, MentionedIn};
+pub use crate::order::CourseOrder;
+pub use crate::dot::{DotColor, DotOptions};
+pub use crate::maps::{representation_map_url, CourseMap, CourseMapGroup, RepresentationMap};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Data {
-    concepts: Vec<Concept>,
-    activities: Vec<Activity>,
-    representations: Vec<Representation>,
-    courses: Vec<Course>,
+    pub(crate) concepts: Vec<Concept>,
+    pub(crate) activities: Vec<Activity>,
+    pub(crate) representations: Vec<Representation>,
+    pub(crate) courses: Vec<Course>,
+    /// What mentions each thing in its description, once we need it.
+    #[serde(skip)]
+    pub(crate) mentions: std::sync::OnceLock<std::collections::BTreeMap<AnyID, Vec<AnyID>>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AnyID {
     Concept(ConceptID),
     Course(CourseID),
@@ -211,10 +353,37 @@ impl AnyID {
             Some('C') => Ok(AnyID::Course(CourseID(s[1..].parse()?))),
             Some('a') => Ok(AnyID::Activity(ActivityID(s[1..].parse()?))),
             Some('r') => Ok(AnyID::Representation(RepresentationID(s[1..].parse()?))),
-            _ => panic!("Crazy kind: {}", s),
+            _ => Err(format!("Crazy kind: {}", s).into()),
+        }
+    }
+}
+impl DisplayAs<HTML> for AnyID {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match self {
+            AnyID::Concept(id) => DisplayAs::<HTML>::fmt(id, f),
+            AnyID::Course(id) => DisplayAs::<HTML>::fmt(id, f),
+            AnyID::Activity(id) => DisplayAs::<HTML>::fmt(id, f),
+            AnyID::Representation(id) => DisplayAs::<HTML>::fmt(id, f),
+            AnyID::ConceptRepresentation(id) => DisplayAs::<HTML>::fmt(id, f),
         }
     }
 }
+impl DisplayAs<URL> for AnyID {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> Result<(), ::std::fmt::Error> {
+        match self {
+            AnyID::Concept(id) => DisplayAs::<URL>::fmt(id, f),
+            AnyID::Course(id) => DisplayAs::<URL>::fmt(id, f),
+            AnyID::Activity(id) => DisplayAs::<URL>::fmt(id, f),
+            AnyID::Representation(id) => DisplayAs::<URL>::fmt(id, f),
+            AnyID::ConceptRepresentation(id) => DisplayAs::<URL>::fmt(id, f),
+        }
+    }
+}
+impl Serialize for AnyID {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_as!(HTML, self))
+    }
+}
 impl std::str::FromStr for AnyID {
     type Err = Box<dyn std::error::Error>;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -222,7 +391,7 @@ impl std::str::FromStr for AnyID {
     }
 }
 
-trait ID: Copy+Clone {
+pub(crate) trait ID: Copy+Clone {
     type Target;
     fn get(self, data: &Data) -> &Self::Target;
     fn get_mut(self, data: &mut Data) -> &mut Self::Target;
@@ -256,25 +425,29 @@ impl ID for RepresentationID {
 }
 impl ID for CourseID {
     type Target = Course;
+    // The courses are kept in the order they are taught, so a
+    // course is not necessarily at its id.
     fn get(self, data: &Data) -> &Self::Target {
-        &data.courses[self.0]
+        data.courses.iter().find(|c| c.id == self).expect("no such course")
     }
     fn get_mut(self, data: &mut Data) -> &mut Self::Target {
-        &mut data.courses[self.0]
+        data.courses.iter_mut().find(|c| c.id == self).expect("no such course")
     }
 }
 
 impl Data {
-    fn get<I: ID>(&self, id: I) -> &I::Target {
+    pub(crate) fn get<I: ID>(&self, id: I) -> &I::Target {
         id.get(self)
     }
-    fn get_mut<I: ID>(&mut self, id: I) -> &mut I::Target {
+    pub(crate) fn get_mut<I: ID>(&mut self, id: I) -> &mut I::Target {
         id.get_mut(self)
     }
     pub fn save(self) {
         let f = AtomicFile::create("progression.yaml").expect("error creating save file");
         serde_yaml::to_writer(&f, &self).expect("error writing yaml");
-        // self.spawn_dump_mirror();
+        drop(f);
+        // Only once the edit is saved does it count towards coverage.
+        crate::coverage::record(self.current_coverage().counts());
     }
     pub fn new() -> Self {
         if let Ok(f) = std::fs::File::open("progression.yaml") {
@@ -287,6 +460,7 @@ impl Data {
             activities: Vec::new(),
             representations: Vec::new(),
             courses: Vec::new(),
+            mentions: std::sync::OnceLock::new(),
         }
     }
     pub fn uploaded_figure(mut self, id: AnyID, filename: &str)
@@ -316,38 +490,80 @@ impl Data {
     }
     pub fn change(mut self, c: Change) -> Result<(), Box<dyn std::error::Error>> {
         println!("change is {:?}", c);
-        match AnyID::parse(&c.id)? {
+        self.apply(&c)?;
+        self.save();
+        Ok(())
+    }
+    /// Make a change without saving it, giving the thing it changed.
+    pub(crate) fn apply(&mut self, c: &Change) -> Result<AnyID, Box<dyn std::error::Error>> {
+        if c.id == "courses" {
+            return match &c.field as &str {
+                "new" => {
+                    let number = c.content.trim();
+                    let name = c.html.trim();
+                    if number.is_empty() {
+                        return Err("A new course needs a number".into());
+                    }
+                    if self.course_by_name(number).is_some() {
+                        return Err(format!("There is already a course {}", number).into());
+                    }
+                    let id = self.course_by_name_or_create(number);
+                    if !name.is_empty() {
+                        self.get_mut(id).name = name.to_string();
+                    }
+                    Ok(AnyID::Course(id))
+                }
+                _ => Err(format!("Weird field for the courses: {}", c.field).into()),
+            };
+        }
+        let changed = AnyID::parse(&c.id)?;
+        match changed {
             AnyID::Course(id) => {
                 match &c.field as &str {
                     "activity" => {
                         let a = self.activity_by_name_or_create(&c.content);
-                        self.add_to_course(id, a);
+                        match c.html.trim().parse() {
+                            Ok(position) => self.insert_into_course(id, a, position),
+                            Err(_) => self.add_to_course(id, a),
+                        }
                     }
-                    "up" => {
+                    "up" | "down" => {
                         match AnyID::parse(&c.content)? {
                             AnyID::Activity(aid) => {
-                                println!("move activity up");
-                                let a = &mut self.get_mut(id).activities;
-                                let which = a.iter().enumerate().filter(|(_,x)| **x == aid)
-                                    .map(|(i,_)| i)
-                                    .next().unwrap();
-                                a.swap(which-1, which);
+                                let which = self.position_in_course(id, aid)?;
+                                let to = if c.field == "up" { which.saturating_sub(1) } else { which + 1 };
+                                self.move_activity(id, aid, to)?;
                             }
-                            _ => panic!("Cannot handle \"up\" with other types"),
+                            _ => return Err(format!("Cannot move {} in a course", c.content).into()),
                         }
                     }
-                    "down" => {
+                    "move" => {
                         match AnyID::parse(&c.content)? {
                             AnyID::Activity(aid) => {
-                                println!("move activity down");
-                                let a = &mut self.get_mut(id).activities;
-                                let which = a.iter().enumerate().filter(|(_,x)| **x == aid)
-                                    .map(|(i,_)| i)
-                                    .next().unwrap();
-                                a.swap(which+1, which);
+                                let to = c.html.trim().parse()
+                                    .map_err(|_| format!("Bad position {:?}", c.html))?;
+                                self.move_activity(id, aid, to)?;
                             }
-                            _ => panic!("Cannot handle \"down\" with other types"),
+                            _ => return Err(format!("Cannot move {} in a course", c.content).into()),
+                        }
+                    }
+                    "order" => {
+                        let mut order = Vec::new();
+                        for a in c.content.split(',').filter(|a| !a.trim().is_empty()) {
+                            match AnyID::parse(a.trim())? {
+                                AnyID::Activity(aid) => order.push(aid),
+                                _ => return Err(format!("Cannot order a course by {}", a).into()),
+                            }
+                        }
+                        let mut sorted = order.clone();
+                        sorted.sort();
+                        let mut current = self.get(id).activities.clone();
+                        current.sort();
+                        if sorted != current {
+                            return Err(format!("{:?} is not an order of the activities of {:?}",
+                                               order, id).into());
                         }
+                        self.get_mut(id).activities = order;
                     }
                     "Remove" => {
                         match c.html.as_ref() {
@@ -362,6 +578,33 @@ impl Data {
                             _ => panic!("Unknown relationship on remove from course: {}", c.html),
                         }
                     }
+                    "name" => {
+                        self.get_mut(id).name = c.content.trim().to_string();
+                    }
+                    "number" => {
+                        let number = c.content.trim();
+                        if number.is_empty() {
+                            return Err("A course needs a number".into());
+                        }
+                        if self.courses().any(|other| other.id != id && other.number == number) {
+                            return Err(format!("There is already a course {}", number).into());
+                        }
+                        self.get_mut(id).number = number.to_string();
+                    }
+                    "term" => {
+                        self.get_mut(id).term = c.content.trim().to_string();
+                    }
+                    "long_description" => {
+                        self.get_mut(id).long_description = Markdown::from_html(&c.html);
+                    }
+                    "position" => {
+                        let to = c.content.trim().parse()
+                            .map_err(|_| format!("Bad position {:?}", c.content))?;
+                        self.move_course(id, to);
+                    }
+                    "delete" => {
+                        self.get_mut(id).deleted = true;
+                    }
                     _ => {
                         panic!("Weird field for course: {}", c.field);
                     }
@@ -581,7 +824,7 @@ impl Data {
             AnyID::Representation(id) => {
                 match &c.field as &str {
                     "icon" => {
-                        self.get_mut(id).icon = Icon::Html(c.html.trim().to_string());
+                        self.get_mut(id).icon = Icon::Html(sanitize_html(c.html.trim()));
                     }
                     "name" => {
                         self.get_mut(id).name = c.content.trim().to_string();
@@ -635,8 +878,7 @@ impl Data {
                 }
             }
         }
-        self.save();
-        Ok(())
+        Ok(changed)
     }
     pub fn concept_by_name(&self, name: &str) -> Option<ConceptID> {
         let name = name.trim();
@@ -718,7 +960,7 @@ impl Data {
     }
     pub fn course_by_name(&self, name: &str) -> Option<CourseID> {
         let name = name.trim();
-        self.courses.iter()
+        self.courses()
             .filter(|c| &c.number == name || &c.name == name)
             .map(|c| c.id)
             .next()
@@ -728,6 +970,30 @@ impl Data {
             self.get_mut(course).activities.push(a);
         }
     }
+    /// Adds the activity to the course just before the activity now at
+    /// `position`, or at the end if the position is past the end.
+    pub fn insert_into_course(&mut self, course: CourseID, a: ActivityID, position: usize) {
+        if !self.get(course).activities.contains(&a) {
+            let activities = &mut self.get_mut(course).activities;
+            activities.insert(std::cmp::min(position, activities.len()), a);
+        }
+    }
+    fn position_in_course(&self, course: CourseID, a: ActivityID)
+                          -> Result<usize, Box<dyn std::error::Error>> {
+        self.get(course).activities.iter().position(|&x| x == a)
+            .ok_or_else(|| format!("{} is not in {}", self.get(a).name,
+                                   self.get(course).name).into())
+    }
+    /// Moves an activity of the course so that it ends up at
+    /// `position`, or at the end if the position is past the end.
+    pub fn move_activity(&mut self, course: CourseID, a: ActivityID, position: usize)
+                         -> Result<(), Box<dyn std::error::Error>> {
+        let which = self.position_in_course(course, a)?;
+        let activities = &mut self.get_mut(course).activities;
+        activities.remove(which);
+        activities.insert(std::cmp::min(position, activities.len()), a);
+        Ok(())
+    }
     pub fn lower_anchor(&mut self, course_name: &str) -> ActivityID {
         let course = self.course_by_name_or_create(course_name);
         let id = self.activity_by_name_or_create(&format!("lower anchor {}", course_name));
@@ -739,7 +1005,7 @@ impl Data {
         if let Some(c) = self.course_by_name(name) {
             return c;
         }
-        let newid = CourseID(self.courses.len());
+        let newid = CourseID(self.courses.iter().map(|c| c.id.0 + 1).max().unwrap_or(0));
         let (number, name) = match name {
             "MTH 251" => (name, "Differential Calculus"),
             "MTH 254" => (name, "Multivariable Calculus"),
@@ -752,7 +1018,10 @@ impl Data {
             id: newid,
             number: number.to_string(),
             name: name.to_string(),
+            term: String::new(),
+            long_description: Markdown::default(),
             activities: Vec::new(),
+            deleted: false,
         });
         newid
     }
@@ -768,13 +1037,29 @@ impl Data {
         self.get_mut(id)
     }
 
-    fn courses_for_concept(&self, id: ConceptID) -> Vec<CourseID> {
-        self.courses.iter()
+    /// Moves a course so it is taught at `position` among the courses
+    /// we have not deleted, or last if the position is past the end.
+    pub fn move_course(&mut self, id: CourseID, position: usize) {
+        let which = self.courses.iter().position(|c| c.id == id).expect("no such course");
+        let course = self.courses.remove(which);
+        let at = self.courses.iter().enumerate().filter(|(_, c)| !c.deleted)
+            .map(|(i, _)| i)
+            .nth(position)
+            .unwrap_or(self.courses.len());
+        self.courses.insert(at, course);
+    }
+    /// The courses in the order they are taught, leaving out any that
+    /// have been deleted.
+    pub(crate) fn courses(&self) -> impl Iterator<Item = &Course> {
+        self.courses.iter().filter(|c| !c.deleted)
+    }
+    pub(crate) fn courses_for_concept(&self, id: ConceptID) -> Vec<CourseID> {
+        self.courses()
             .filter(|c| self.course_is_for_concept(id.into(), c.id))
             .map(|c| c.id)
             .collect()
     }
-    fn course_is_for_concept(&self, nid: ConceptRepresentationID, rid: CourseID) -> bool {
+    pub(crate) fn course_is_for_concept(&self, nid: ConceptRepresentationID, rid: CourseID) -> bool {
         for a in self.get(rid).activities.iter() {
             if self.get(*a).new_concepts.contains(&nid) {
                 return true;
@@ -782,13 +1067,85 @@ impl Data {
         }
         false
     }
-    pub fn concept_map(&self, max_width: usize, num_iters: usize) -> ConceptMap
-    {
+    /// The prerequisite edges between concepts, from the prerequisite.
+    pub(crate) fn prereq_edges(&self) -> Vec<(ConceptID, ConceptID)> {
         let mut edges = Vec::new();
         for c in self.concepts.iter() {
             edges.extend(c.prereq_concepts.iter().map(|&pre| (pre, c.id)));
         }
+        edges
+    }
+    /// The concept map with no more than `max_width` concepts in a
+    /// row, laid out again first if the concepts have changed.
+    pub fn concept_map(&self, max_width: usize) -> ConceptMap {
+        let layout = self.concept_layout(max_width, true);
+        self.concept_map_from(&layout, max_width).make_expensive(self)
+    }
+    /// The concepts that a layout leaves out, since they are not
+    /// connected to any other.
+    pub(crate) fn unconnected(&self, layout: &Layout<ConceptID>) -> Vec<ConceptID> {
+        let placed: std::collections::BTreeSet<ConceptID> = layout.layers.iter().flatten()
+            .filter_map(|&(s, _)| if let Slot::Node(c) = s { Some(c) } else { None })
+            .collect();
+        self.concepts.iter().map(|c| c.id).filter(|c| !placed.contains(c)).collect()
+    }
+    /// The concept map as laid out from scratch.
+    pub fn layered_concept_map(&self, max_width: usize) -> ConceptMap {
+        let layout = crate::layout::untangled(&self.prereq_edges(), max_width, &[]);
+        self.concept_map_from(&layout, max_width)
+    }
+    pub(crate) fn concept_map_from(&self, layout: &Layout<ConceptID>, max_width: usize) -> ConceptMap {
+        let mut rows = self.concept_rows(layout);
+        let orphans: Vec<_> = self.unconnected(layout).into_iter()
+            .map(|concept| ConceptNode::CheapConcept {
+                concept,
+                children: Vec::new(),
+            })
+            .collect();
+        for orph in orphans.chunks(max_width.max(1)) {
+            rows.push(orph.to_vec());
+        }
+        ConceptMap { rows, focus: None, options: None }
+    }
+    /// The rows of the concept map for each layer of a layout.
+    pub(crate) fn concept_rows(&self, layout: &Layout<ConceptID>) -> Vec<Vec<ConceptNode>> {
+        let mut next_fakeid = self.concepts.iter().map(|c| c.id.0 + 1).max().unwrap_or(0);
+        // An edge has a dummy in each layer it passes over, so we
+        // number the dummies layer by layer.
+        let mut ids = std::collections::BTreeMap::new();
+        for (l, layer) in layout.layers.iter().enumerate() {
+            for &(slot, _) in layer.iter() {
+                let id = match slot {
+                    Slot::Node(c) => c.into(),
+                    Slot::Dummy(..) => NodeID({ next_fakeid += 1; next_fakeid }),
+                };
+                ids.insert((l, slot), id);
+            }
+        }
+        layout.layers.iter().enumerate()
+            .map(|(l, layer)| layer.iter().map(|&(slot, _)| {
+                let mut children = layout.children(l, slot).into_iter().map(|c| ids[&(l + 1, c)]);
+                match slot {
+                    Slot::Node(concept) => ConceptNode::CheapConcept {
+                        concept,
+                        children: children.collect(),
+                    },
+                    Slot::Dummy(..) => ConceptNode::Fake {
+                        fakeid: ids[&(l, slot)],
+                        child: children.next().expect("a dummy always leads somewhere"),
+                    },
+                }
+            }).collect())
+            .collect()
+    }
+    /// The concept map as we used to lay it out, by randomly swapping
+    /// concepts within rows to reduce crossings.  This is slow, but
+    /// we keep it to compare against (see benches/layout.rs).
+    pub fn annealed_concept_map(&self, max_width: usize, num_iters: usize) -> ConceptMap
+    {
+        let edges = self.prereq_edges();
         let layers = layer_concepts(edges.clone(), max_width);
+        let graph = stable_hash(format!("{} {:?}", max_width, edges).as_bytes());
 
         let concepts: Vec<ConceptID> = layers.iter().flat_map(|x| x.iter().cloned()).collect();
         // FIXME I should first ensure there is no cycle in the
@@ -809,7 +1166,7 @@ impl Data {
         let mut next_fakeid = self.concepts.len();
         if layers.len() == 0 {
             println!("Why are there no layers?!");
-            return ConceptMap { rows };
+            return ConceptMap { rows, focus: None, options: None };
         }
         for i in 0..layers.len()-1 {
             let mut this_layer = Vec::new();
@@ -864,11 +1221,15 @@ impl Data {
                 children: Vec::new(),
             })
             .collect();
-        let mut cmap = ConceptMap { rows }.optimize(num_iters);
+        // We seed the optimization with the graph itself, so the same
+        // graph always gives the same map.
+        use rand::SeedableRng;
+        let mut cmap = ConceptMap { rows, focus: None, options: None }
+            .optimize(num_iters, &mut rand::rngs::StdRng::seed_from_u64(graph));
         for orph in orphans.chunks(max_width) {
             cmap.rows.push(orph.to_vec());
         }
-        cmap.make_expensive(self)
+        cmap
     }
 
     pub fn concept_representation_view(&self, parent: impl Copy+DisplayAs<HTML>,
@@ -887,7 +1248,7 @@ impl Data {
                     .cloned().collect(),
                 name: rr.name.clone(),
                 summary_name: self.name_it(id),
-                long_description: rr.long_description.clone(),
+                long_description: self.with_references(&rr.long_description),
                 figure: rr.figure.clone(),
             })
         } else {
@@ -899,7 +1260,7 @@ impl Data {
                     .cloned().collect(),
                 name: c.name.clone(),
                 summary_name: c.name.clone(),
-                long_description: c.long_description.clone(),
+                long_description: self.with_references(&c.long_description),
                 figure: c.figure.clone(),
             })
         }
@@ -935,19 +1296,165 @@ impl Data {
             None
         }
     }
-    fn untaught_concept_representations(&self) -> Vec<ConceptRepresentationID> {
-        let mut ids: Vec<ConceptRepresentationID> = self.concepts.iter()
-            .map(|c| c.id.into())
-            .chain(self.concepts.iter()
-                   .flat_map(|c| {
-                       let cid = c.id;
-                       c.representations.keys()
-                           .map(move |&x| (cid, x).into())
-                   }))
+    /// Every piece of markdown we store, along with what it describes.
+    pub fn descriptions(&self) -> Vec<(AnyID, &Markdown)> {
+        let mut out = Vec::new();
+        for c in self.concepts.iter() {
+            out.push((AnyID::Concept(c.id), &c.long_description));
+            for (&rid, r) in c.representations.iter() {
+                out.push((AnyID::ConceptRepresentation((c.id, rid).into()),
+                          &r.long_description));
+            }
+        }
+        for a in self.activities.iter() {
+            out.push((AnyID::Activity(a.id), &a.long_description));
+        }
+        for r in self.representations.iter() {
+            out.push((AnyID::Representation(r.id), &r.description));
+        }
+        for c in self.courses() {
+            out.push((AnyID::Course(c.id), &c.long_description));
+        }
+        out
+    }
+    /// Whether an id refers to something we actually have.
+    pub(crate) fn has(&self, id: AnyID) -> bool {
+        match id {
+            AnyID::Concept(id) => id.0 < self.concepts.len(),
+            AnyID::Course(id) => self.courses().any(|c| c.id == id),
+            AnyID::Activity(id) => id.0 < self.activities.len(),
+            AnyID::Representation(id) => id.0 < self.representations.len(),
+            AnyID::ConceptRepresentation(id) => {
+                id.concept.0 < self.concepts.len()
+                    && id.representation.map(|rid| self.get(id.concept)
+                                             .representations.contains_key(&rid))
+                    .unwrap_or(true)
+            }
+        }
+    }
+    /// The current name of whatever an id refers to.
+    pub fn name_of(&self, id: AnyID) -> String {
+        match id {
+            AnyID::Concept(id) => self.get(id).name.clone(),
+            AnyID::Course(id) => self.get(id).name.clone(),
+            AnyID::Activity(id) => self.get(id).name.clone(),
+            AnyID::Representation(id) => self.get(id).name.clone(),
+            AnyID::ConceptRepresentation(id) => self.name_it(id),
+        }
+    }
+    /// Find what a `[[reference]]` refers to, which is either an id
+    /// (like c2 or a7) or the name of a concept.
+    pub fn resolve_reference(&self, reference: &str) -> Option<AnyID> {
+        fn words(s: &str) -> Vec<&str> {
+            s.split_whitespace().collect()
+        }
+        AnyID::parse(reference.trim()).ok().filter(|&id| self.has(id))
+            .or_else(|| self.concept_by_name(reference).map(AnyID::Concept))
+            .or_else(|| self.concepts.iter()
+                     .find(|c| words(&c.name) == words(reference))
+                     .map(|c| AnyID::Concept(c.id)))
+    }
+    /// Turn the references in a description into links.
+    fn with_references(&self, md: &Markdown) -> Markdown {
+        md.with_references(|r| self.resolve_reference(r).map(|id| {
+            (format_as!(HTML, absolute_url(id)), self.name_of(id))
+        }))
+    }
+    /// The stored data for something, along with what mentions it,
+    /// as json.
+    pub fn json(&self, id: AnyID) -> Option<String> {
+        #[derive(Serialize)]
+        struct WithMentions<'a, T> {
+            #[serde(flatten)]
+            data: &'a T,
+            mentioned_in: Vec<Mention>,
+        }
+        if !self.has(id) {
+            return None;
+        }
+        let mentioned_in = self.mentions_of(id).0;
+        let json = match id {
+            AnyID::Concept(id) => serde_json::to_string_pretty(
+                &WithMentions { data: self.get(id), mentioned_in }),
+            AnyID::Activity(id) => serde_json::to_string_pretty(
+                &WithMentions { data: self.get(id), mentioned_in }),
+            AnyID::Representation(id) => serde_json::to_string_pretty(
+                &WithMentions { data: self.get(id), mentioned_in }),
+            _ => return None,
+        };
+        Some(json.expect("trouble generating json"))
+    }
+    /// Every concept, activity, representation and course, as json,
+    /// so we can notice when one has changed.
+    pub(crate) fn entity_json(&self) -> Vec<(AnyID, String)> {
+        fn json(x: &impl Serialize) -> String {
+            serde_json::to_string(x).expect("trouble generating json")
+        }
+        self.concepts.iter().map(|x| (AnyID::Concept(x.id), json(x)))
+            .chain(self.activities.iter().map(|x| (AnyID::Activity(x.id), json(x))))
+            .chain(self.representations.iter().map(|x| (AnyID::Representation(x.id), json(x))))
+            .chain(self.courses().map(|x| (AnyID::Course(x.id), json(x))))
+            .collect()
+    }
+    /// Pairs of things that are shown on one another's pages,
+    /// either because they are related or because one mentions the
+    /// other.
+    pub(crate) fn links(&self) -> Vec<(AnyID, AnyID)> {
+        let concept = |cr: &ConceptRepresentationID| AnyID::Concept(cr.concept);
+        let mut out = Vec::new();
+        for c in self.concepts.iter() {
+            let id = AnyID::Concept(c.id);
+            out.extend(c.prereq_concepts.iter().map(|&p| (id, AnyID::Concept(p))));
+            out.extend(c.representations.keys().map(|&r| (id, AnyID::Representation(r))));
+        }
+        for a in self.activities.iter() {
+            let id = AnyID::Activity(a.id);
+            for cr in a.new_concepts.iter().chain(a.prereq_concepts.iter()) {
+                out.push((id, concept(cr)));
+                out.extend(cr.representation.map(|r| (id, AnyID::Representation(r))));
+            }
+            out.extend(a.representations.iter().map(|&r| (id, AnyID::Representation(r))));
+        }
+        for c in self.courses() {
+            out.extend(c.activities.iter().map(|&a| (AnyID::Course(c.id), AnyID::Activity(a))));
+        }
+        for (&to, froms) in self.mentions().iter() {
+            let to = match to {
+                AnyID::ConceptRepresentation(cr) => concept(&cr),
+                to => to,
+            };
+            out.extend(froms.iter().map(|&from| (from, to)));
+        }
+        out
+    }
+    /// The files of every figure and icon we show.
+    pub(crate) fn figures(&self) -> Vec<String> {
+        let mut out: Vec<String> = self.concepts.iter()
+            .flat_map(|c| c.figure.iter()
+                      .chain(c.representations.values().flat_map(|r| r.figure.iter())))
+            .chain(self.activities.iter().flat_map(|a| a.figure.iter()))
+            .map(|f| format!("figs/{}", f))
+            .chain(self.representations.iter().filter_map(|r| match &r.icon {
+                Icon::Fig(f) => Some(f.clone()),
+                Icon::Html(_) => None,
+            }))
             .collect();
-        ids.sort();
-        ids.dedup();
-        ids
+        out.sort();
+        out.dedup();
+        out
+    }
+    /// The concept a reference refers to, which may be its id (with or
+    /// without the c), or its name.
+    pub fn concept_reference(&self, reference: &str) -> Option<ConceptID> {
+        let reference = reference.trim();
+        if let Ok(n) = reference.parse() {
+            return Some(ConceptID(n)).filter(|&id| self.has(AnyID::Concept(id)));
+        }
+        match self.resolve_reference(reference)? {
+            AnyID::Concept(id) => Some(id),
+            AnyID::ConceptRepresentation(cr) => Some(cr.concept),
+            _ => None,
+        }
     }
     pub fn all_concept_representations(&self, id: impl Copy+DisplayAs<HTML>, field: &str)
                                        -> ConceptRepresentationChoice {
@@ -1019,11 +1526,13 @@ impl Data {
                                                  id, "with"),
             courses: self.courses_for_concept(c.id).iter().map(|&cid| self.get(cid).clone()).collect(),
             figure: c.figure.clone(),
-            long_description: c.long_description.clone(),
+            long_description: self.with_references(&c.long_description),
+            mentioned_in: self.mentions_of(AnyID::Concept(id)),
+            implied_prereqs: self.implied_prereqs_into(Some(id)),
         };
         // We haven't generated this view yet, so we need to add the
         // related concepts.
-        view.prereq_courses = self.courses.iter().cloned()
+        view.prereq_courses = self.courses().cloned()
             .map(|course| PrereqCourse {
                 course: course.clone(),
                 concepts: my_prereq_concepts.iter()
@@ -1072,7 +1581,7 @@ impl Data {
         let a = &self.get(id);
         let prereq_concepts_in_this_course: Vec<_> =
             a.prereq_concepts.iter().cloned()
-            .filter(|&cid| self.courses.iter()
+            .filter(|&cid| self.courses()
                     .filter(|xx| xx.activities.contains(&id))
                     .any(|cc| self.course_is_for_concept(cid, cc.id)))
             .collect();
@@ -1088,7 +1597,7 @@ impl Data {
                 choices: self.representations.iter().map(|c| c.name.clone()).collect(),
             },
 
-            prereq_courses: self.courses.iter().cloned()
+            prereq_courses: self.courses().cloned()
                 .filter(|course| !course.activities.contains(&a.id))
                 .map(|course| {
                     use std::collections::BTreeSet;
@@ -1117,10 +1626,11 @@ impl Data {
 
             representations: a.representations.iter()
                 .map(|&rid| Child::remove(id, "uses", self.get(rid).clone())).collect(),
-            courses: self.courses.iter().filter(|c| c.activities.contains(&id))
+            courses: self.courses().filter(|c| c.activities.contains(&id))
                 .cloned().collect(),
             figure: a.figure.clone(),
-            long_description: a.long_description.clone(),
+            long_description: self.with_references(&a.long_description),
+            mentioned_in: self.mentions_of(AnyID::Activity(id)),
             external_url: a.external_url.clone(),
         };
 
@@ -1163,7 +1673,8 @@ impl Data {
         RepresentationView {
             id,
             name: r.name,
-            description: r.description,
+            description: self.with_references(&r.description),
+            mentioned_in: self.mentions_of(AnyID::Representation(id)),
             icon: r.icon,
             other_concepts,
             groups,
@@ -1171,11 +1682,11 @@ impl Data {
     }
 
     pub fn progression_view(&self) -> ProgressionView {
-        let courses: Vec<_> = self.courses.iter().map(|c| self.course_sequence(c.id))
+        let courses: Vec<_> = self.courses().map(|c| self.course_sequence(c.id))
             .filter(|x| x.groups.len() > 1) // FIXME should handle prereqs better?
             .collect();
-        let prereq_courses: Vec<_> = self.courses.iter()
-            .take(courses[0].course.id.0).cloned()
+        let prereq_courses: Vec<_> = self.courses()
+            .take_while(|c| Some(c.id) != courses.first().map(|s| s.course.id)).cloned()
             .map(|course| {
                 use std::collections::BTreeSet;
                 let crs: BTreeSet<ConceptRepresentationID> =
@@ -1194,12 +1705,15 @@ impl Data {
     }
 
     pub fn course_sequence(&self, id: CourseID) -> CourseSequence {
-        let course = self.get(id).clone();
+        let mut course = self.get(id).clone();
+        course.long_description = self.with_references(&course.long_description);
         let course_activities: Vec<_> = course.activities.iter()
             .map(|&a| self.activity_view(a)).collect();
+        let warnings = self.prereq_warnings(id);
         let mut groups = Vec::new();
         for a in course_activities.into_iter() {
             groups.push(ProgressionGroup {
+                warnings: warnings.iter().filter(|w| w.activity.id == a.id).cloned().collect(),
                 concepts: a.new_concepts.iter()
                     .map(|c| self.concept_representation_view(a.id, "new", c.id.into()))
                     .collect(),
@@ -1211,10 +1725,31 @@ impl Data {
             field: "activity".to_string(),
             choices: self.activities.iter().filter(|a| !course.activities.contains(&a.id))
                 .cloned().collect(),
+            positions: course.activities.iter().map(|&a| self.get(a).name.clone()).collect(),
         };
         CourseSequence { course, prereq_courses: Vec::new(), new_activity, groups }
     }
 
+    pub fn course_list(&self) -> CourseList {
+        CourseList {
+            courses: self.courses().cloned()
+                .map(|mut c| {
+                    c.long_description = self.with_references(&c.long_description);
+                    c
+                })
+                .collect(),
+        }
+    }
+    /// Whether there is such a course, which there is not once it is
+    /// deleted.
+    pub fn has_course(&self, id: CourseID) -> bool {
+        self.has(AnyID::Course(id))
+    }
+    /// Whether there is such a concept.
+    pub fn has_concept(&self, id: ConceptID) -> bool {
+        self.has(AnyID::Concept(id))
+    }
+
     pub fn course_view(&self, id: CourseID) -> CourseSequence {
         let mut cs = self.course_sequence(id);
 
@@ -1227,7 +1762,7 @@ impl Data {
         let my_prereq_concepts: Vec<Child<ConceptRepresentationView>> =
             my_prereq_concepts.iter().map(|&cr| self.concept_representation_view(id, "prereq", cr)).collect();
 
-        cs.prereq_courses = self.courses.iter()
+        cs.prereq_courses = self.courses()
             .filter(|course| course.id != id)
             .map(|course| PrereqCourse {
                 course: course.clone(),
@@ -1308,58 +1843,9 @@ impl Data {
             concepts: Vec::new(),
         });
     }
-
-    pub fn spawn_dump_mirror(self) {
-        use std::sync::atomic::{AtomicBool, Ordering};
-
-        static AM_MIRRORING: AtomicBool = AtomicBool::new(false);
-        if !AM_MIRRORING.compare_and_swap(false, true, Ordering::Relaxed) {
-            std::thread::spawn(move || {
-                self.dump_mirror();
-                AM_MIRRORING.store(false, Ordering::Relaxed);
-            });
-        }
-    }
-    pub fn dump_mirror(self) {
-        std::fs::create_dir_all("mirror/concept").unwrap();
-        println!("creating concepts...");
-        for a in self.concepts.iter() {
-            std::fs::write(format_as!(URL, "mirror/" a),
-                           format_as!(HTML, self.concept_view(a.id))).ok();
-        }
-        println!("creating activities...");
-        std::fs::create_dir_all("mirror/activity").unwrap();
-        for a in self.activities.iter() {
-            std::fs::write(format_as!(URL, "mirror/" a),
-                           format_as!(HTML, self.activity_view(a.id))).ok();
-        }
-        println!("creating courses...");
-        std::fs::create_dir_all("mirror/course").unwrap();
-        for a in self.courses.iter() {
-            std::fs::write(format_as!(URL, "mirror/" a),
-                           format_as!(HTML, self.course_view(a.id))).ok();
-        }
-        println!("creating representations...");
-        std::fs::create_dir_all("mirror/representation").unwrap();
-        for a in self.representations.iter() {
-            std::fs::write(format_as!(URL, "mirror/" a),
-                           format_as!(HTML, self.representation_view(a.id))).ok();
-        }
-        {
-            std::fs::write(format_as!(URL, "mirror/index.html"),
-                           format_as!(HTML, self.progression_view())).ok();
-        }
-        std::fs::create_dir_all("mirror/concept-map").unwrap();
-        for max_width in 1..18 {
-            std::fs::write(format_as!(URL, "mirror/concept-map/" max_width),
-                           format_as!(HTML, self.concept_map(max_width, 1 << 20))).ok();
-        }
-        std::fs::write(format_as!(URL, "mirror/concept-map/index.html"),
-                       format_as!(HTML, self.concept_map(4, 1 << 20))).ok();
-        println!("All done with the mirror!");
-    }
 }
 
+
 /// This is a course and concepts it teaches.
 #[derive(Debug, Clone)]
 pub struct PrereqCourse {
@@ -1393,6 +1879,8 @@ impl DisplayAs<HTML> for ActivityGroup {}
 pub struct ProgressionGroup {
     pub activity: Child<ActivityView>,
     pub concepts: Vec<Child<ConceptRepresentationView>>,
+    /// The prerequisites of the activity that haven't been introduced.
+    pub warnings: Vec<PrereqWarning>,
 }
 #[with_template("[%" "%]" "progression-group.html")]
 impl DisplayAs<HTML> for ProgressionGroup {}
@@ -1406,6 +1894,14 @@ pub struct CourseSequence {
 #[with_template("[%" "%]" "course-sequence.html")]
 impl DisplayAs<HTML> for CourseSequence {}
 
+
+/// Every course, in the order they are taught.
+pub struct CourseList {
+    courses: Vec<Course>,
+}
+#[with_template("[%" "%]" "courses.html")]
+impl DisplayAs<HTML> for CourseList {}
+
 pub struct ProgressionView {
     prereq_courses: Vec<PrereqCourse>,
     courses: Vec<CourseSequence>,
@@ -1437,6 +1933,9 @@ pub struct ActivityChoice {
     pub id: String,
     pub field: String,
     pub choices: Vec<Activity>,
+    /// The names of the activities the new one could go before, if
+    /// the order matters.
+    pub positions: Vec<String>,
 }
 #[with_template("[%" "%]" "activity-choice.html")]
 impl DisplayAs<HTML> for ActivityChoice {}
@@ -1621,35 +2120,6 @@ impl DisplayAs<URL> for Child<Representation> {}
 impl DisplayAs<HTML> for Child<Representation> {}
 
 
-// The following enables visualization of graphs.
-impl<'a> dot::GraphWalk<'a,ConceptID,(ConceptID,ConceptID)> for Data {
-    fn nodes(&'a self) -> dot::Nodes<'a,ConceptID> {
-        // Only include concepts that have *some* dependency.
-        let mut out: Vec<_> = self.concepts.iter().flat_map(|c| c.prereq_concepts.clone()).collect();
-        for c in self.concepts.iter() {
-            if c.prereq_concepts.len() > 0 {
-                out.push(c.id);
-            }
-        }
-        out.sort();
-        out.dedup();
-        out.into()
-    }
-    fn edges(&'a self) -> dot::Nodes<'a,(ConceptID,ConceptID)> {
-        let mut out = Vec::new();
-        for c in self.concepts.iter() {
-            out.extend(c.prereq_concepts.iter().map(|&pre| (pre, c.id)));
-        }
-        out.into()
-    }
-    fn source(&self, edge: &(ConceptID, ConceptID)) -> ConceptID {
-        edge.0
-    }
-    fn target(&self, edge: &(ConceptID, ConceptID)) -> ConceptID {
-        edge.1
-    }
-}
-
 /// An attempt at Coffman-Graham
 pub fn layer_concepts(edges: Vec<(ConceptID, ConceptID)>,
                       max_width: usize)
@@ -1805,14 +2275,14 @@ pub enum ConceptNode {
 impl DisplayAs<HTML> for ConceptNode {}
 
 impl ConceptNode {
-    fn is_fake(&self) -> bool {
+    pub(crate) fn is_fake(&self) -> bool {
         match self {
             ConceptNode::Concept{..} => false,
             ConceptNode::CheapConcept{..} => false,
             ConceptNode::Fake{..} => true,
         }
     }
-    fn make_expensive(self, data: &Data) -> Self {
+    pub(crate) fn make_expensive(self, data: &Data) -> Self {
         match self {
             ConceptNode::Concept{..} => self,
             ConceptNode::CheapConcept{ concept, children} => {
@@ -1856,10 +2326,50 @@ impl ConceptNode {
 
 #[derive(Clone)]
 pub struct ConceptMap {
-    rows: Vec<Vec<ConceptNode>>,
+    pub(crate) rows: Vec<Vec<ConceptNode>>,
+    /// The concept a map of its neighborhood is centered on.
+    pub(crate) focus: Option<Focus>,
+    /// How the whole concept map was drawn, when it may be drawn
+    /// another way.
+    pub(crate) options: Option<MapOptions>,
+}
+
+#[derive(Clone, Copy)]
+pub struct MapOptions {
+    pub(crate) max_width: usize,
+    pub(crate) hide_implied: bool,
+}
+
+#[derive(Clone)]
+pub struct Focus {
+    pub(crate) concept: Concept,
+    /// How many prerequisite steps away the map reaches.
+    pub(crate) depth: usize,
+    /// Whether there is more to see a step further away.
+    pub(crate) further: bool,
 }
+
+/// A hash that (unlike the standard library hasher) is guaranteed
+/// not to change between versions of rust.  This is FNV-1a.
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100_0000_01b3);
+    }
+    h
+}
+
 #[with_template("[%" "%]" "concept-map.html")]
 impl DisplayAs<HTML> for ConceptMap {}
+
+
+/// Hues spread out around the color wheel, so that neighboring
+/// representations look different.
+pub(crate) fn representation_hue(id: RepresentationID) -> usize {
+    id.0*137 % 360
+}
+
 const SCALE: usize = 2;
 const DISTANCE_SCALE: usize = 1000000;
 impl ConceptMap {
@@ -1869,10 +2379,24 @@ impl ConceptMap {
                 .map(|r| r.into_iter()
                      .map(|node| node.make_expensive(data))
                      .collect())
-                .collect()
+                .collect(),
+            focus: self.focus,
+            options: self.options,
         }
     }
     pub fn crossings(&self, verbose: bool) -> usize {
+        let (cross, distance) = self.crossings_and_distance();
+        if verbose {
+            let d = distance as f64/DISTANCE_SCALE as f64;
+            println!("   {} + {} = {}", cross, d, cross as f64 + d);
+        }
+        cross*SCALE + distance*SCALE/DISTANCE_SCALE
+    }
+    /// The number of pairs of lines that cross.
+    pub fn num_crossings(&self) -> usize {
+        self.crossings_and_distance().0
+    }
+    fn crossings_and_distance(&self) -> (usize, usize) {
         let mut cross = 0;
         let mut distance = 0;
         let findx = |ww: &[ConceptNode], ix| {
@@ -1911,22 +2435,18 @@ impl ConceptMap {
                 }
             }
         }
-        if verbose {
-            let d = distance as f64/DISTANCE_SCALE as f64;
-            println!("   {} + {} = {}", cross, d, cross as f64 + d);
-        }
-        cross*SCALE + distance*SCALE/DISTANCE_SCALE
+        (cross, distance)
     }
-    pub fn random_change(&self) -> Self {
+    pub fn random_change(&self, rng: &mut impl rand::Rng) -> Self {
         let mut out = self.clone();
-        let to_change: usize = rand::random::<usize>() % out.rows.len();
+        let to_change: usize = rng.gen_range(0, out.rows.len());
         let rowlen: usize = out.rows[to_change].len();
-        let to_swap1 = rand::random::<usize>() % rowlen;
-        let to_swap2 = rand::random::<usize>() % rowlen;
+        let to_swap1 = rng.gen_range(0, rowlen);
+        let to_swap2 = rng.gen_range(0, rowlen);
         out.rows[to_change].swap(to_swap1, to_swap2);
         out
     }
-    pub fn optimize(&self, num_iters: usize) -> Self {
+    pub fn optimize(&self, num_iters: usize, rng: &mut impl rand::Rng) -> Self {
         let mut best = self.clone();
         let mut current = self.clone();
         let mut e_best = best.crossings(false);
@@ -1934,7 +2454,7 @@ impl ConceptMap {
         let mut logw = std::collections::BTreeMap::new();
         logw.insert(e_best, 1);
         for i in 0..num_iters {
-            let trial = current.random_change();
+            let trial = current.random_change(rng);
             let e_trial = trial.crossings(false);
             let logw_old = logw.get(&e).unwrap_or(&0);
             let logw_new = logw.get(&e_trial).unwrap_or(&0);
@@ -1972,22 +2492,132 @@ impl ConceptMap {
     }
 }
 
-impl<'a> dot::Labeller<'a, ConceptID, (ConceptID, ConceptID)> for Data {
-    fn graph_id(&'a self) -> dot::Id<'a> {
-        dot::Id::new("concept_map").expect("trouble with graph_id?")
-    }
-    fn node_id(&'a self, n: &ConceptID) -> dot::Id<'a> {
-        dot::Id::new(format_as!(HTML, n)).expect("trouble with node_id??")
-    }
-    fn node_label<'b>(&'b self, n: &ConceptID) -> dot::LabelText<'b> {
-        dot::LabelText::HtmlStr(format_as!(HTML, self.get(*n).name).into())
-    }
-    fn edge_label<'b>(&'b self, _: &(ConceptID, ConceptID)) -> dot::LabelText<'b> {
-        dot::LabelText::LabelStr("".into())
+#[test]
+fn concept_map_layout_is_reproducible() {
+    let data = Data::new();
+    let ids = |cmap: &ConceptMap| -> Vec<Vec<usize>> {
+        cmap.rows.iter().map(|r| r.iter().map(|n| n.id().0).collect()).collect()
+    };
+    let layered = ids(&data.layered_concept_map(4));
+    assert_eq!(layered, ids(&data.layered_concept_map(4)));
+    let mut concepts: Vec<usize> = layered.into_iter().flatten()
+        .filter(|&id| id < data.concepts.len())
+        .collect();
+    concepts.sort();
+    assert_eq!(concepts, data.concepts.iter().map(|c| c.id.0).collect::<Vec<_>>());
+    let mut all: Vec<usize> = ids(&data.layered_concept_map(4)).into_iter().flatten().collect();
+    let len = all.len();
+    all.sort();
+    all.dedup();
+    assert_eq!(all.len(), len, "every node in the map needs its own id");
+    assert_eq!(ids(&data.annealed_concept_map(4, 1000)), ids(&data.annealed_concept_map(4, 1000)));
+}
+
+#[test]
+fn layered_map_untangles_the_wider_maps() {
+    // The random swaps of annealed_concept_map find no crossings at
+    // these widths either (see benches/layout.rs).
+    let data = Data::new();
+    for &width in &[4, 8, 16] {
+        assert_eq!(data.layered_concept_map(width).num_crossings(), 0);
     }
 }
 
+
+
+
+
+
+
+
+
+#[test]
+fn move_activity_stays_in_bounds() {
+    let mut data = Data::new();
+    let id = data.courses.iter().find(|c| c.activities.len() > 2).unwrap().id;
+    let original = data.get(id).activities.clone();
+    let (first, last) = (original[0], original[original.len()-1]);
+    data.move_activity(id, first, 0).unwrap();
+    assert_eq!(data.get(id).activities, original);
+    data.move_activity(id, last, original.len() + 5).unwrap();
+    assert_eq!(data.get(id).activities, original);
+    data.move_activity(id, last, 1).unwrap();
+    assert_eq!(&data.get(id).activities[..3], &[first, last, original[1]]);
+    data.move_activity(id, last, original.len() - 1).unwrap();
+    assert_eq!(data.get(id).activities, original);
+
+    let other = data.activities.iter().map(|a| a.id)
+        .find(|a| !original.contains(a)).unwrap();
+    assert!(data.move_activity(id, other, 0).is_err());
+    data.insert_into_course(id, other, 1);
+    assert_eq!(&data.get(id).activities[..3], &[first, other, original[1]]);
+    // Adding it again leaves it where it is.
+    data.insert_into_course(id, other, 0);
+    assert_eq!(data.get(id).activities[1], other);
+}
+
+#[test]
+fn courses_keep_their_ids_when_moved_or_deleted() {
+    let mut data = Data::new();
+    let ids: Vec<CourseID> = data.courses().map(|c| c.id).collect();
+    let names: Vec<String> = data.courses().map(|c| c.name.clone()).collect();
+    data.move_course(ids[0], 2);
+    let moved: Vec<CourseID> = data.courses().map(|c| c.id).collect();
+    assert_eq!(&moved[..3], &[ids[1], ids[2], ids[0]]);
+    assert_eq!(data.get(ids[0]).name, names[0]);
+
+    data.get_mut(ids[1]).deleted = true;
+    assert!(!data.has_course(ids[1]));
+    assert!(data.courses().all(|c| c.id != ids[1]));
+    assert!(data.course_by_name(&names[1]).is_none());
+    // A position counts only the courses we still have.
+    data.move_course(ids[0], 0);
+    assert_eq!(data.courses().next().unwrap().id, ids[0]);
+    data.move_course(ids[0], ids.len() + 3);
+    assert_eq!(data.courses().last().unwrap().id, ids[0]);
+
+    let new = data.course_by_name_or_create("PH 999");
+    assert!(!ids.contains(&new));
+    assert!(data.has_course(new));
+
+    // With no courses left, there is still a progression to show.
+    for course in data.courses.iter_mut() {
+        course.deleted = true;
+    }
+    assert!(data.progression_view().courses.is_empty());
+}
+
+#[test]
+fn courses_can_be_created_and_renumbered() {
+    let mut data = Data::new();
+    let change = |id: &str, field: &str, content: &str, html: &str| Change {
+        id: id.to_string(),
+        field: field.to_string(),
+        content: content.to_string(),
+        html: html.to_string(),
+    };
+    let ids: Vec<CourseID> = data.courses().map(|c| c.id).collect();
+    for &id in ids.iter() {
+        data.apply(&change(&format_as!(HTML, id), "delete", "", "")).unwrap();
+    }
+    assert_eq!(data.courses().count(), 0);
+    assert!(format_as!(HTML, data.course_list()).contains("name=\"field\" value=\"new\""));
+
+    let new = data.apply(&change("courses", "new", "PH 999", "Testing")).unwrap();
+    assert_eq!(data.courses().map(|c| AnyID::Course(c.id)).collect::<Vec<_>>(), vec![new]);
+    assert!(data.apply(&change("courses", "new", "PH 999", "Again")).is_err());
+    assert!(data.apply(&change("courses", "new", " ", "No number")).is_err());
+
+    let other = data.apply(&change("courses", "new", "PH 998", "Other")).unwrap();
+    let other = format_as!(HTML, other);
+    assert!(data.apply(&change(&other, "number", "PH 999", "")).is_err());
+    data.apply(&change(&other, "number", "PH 997", "")).unwrap();
+    assert!(data.course_by_name("PH 997").is_some());
+}
+
+
 struct List<T>(Vec<T>);
 
 #[with_template("[%" "%]" "list.html")]
 impl<T: DisplayAs<HTML>>  DisplayAs<HTML> for List<T> {}
+
@@ -0,0 +1,225 @@
+//! Copying the mirror to wherever the site is served from, touching
+//! only the files that changed, and undoing that if need be.
+//!
+//! What we need to undo a deploy lives in a state directory of its
+//! own, outside the target, so that old versions of pages are never
+//! published.  Each target needs its own state directory.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use crate::mirror::files_in;
+
+/// The content hash of every file, by its path relative to the top.
+type Manifest = BTreeMap<String, String>;
+
+/// What a deploy (or rollback) did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deployed {
+    pub copied: usize,
+    pub removed: usize,
+}
+
+/// A hash of a file's contents.  This is FNV-1a, which unlike the
+/// standard library hasher is guaranteed not to change between
+/// versions of rust.  It is only a quick way to spot a change: two
+/// files with the same hash may still differ, so before skipping a
+/// copy we compare the bytes.
+fn content_hash(bytes: &[u8]) -> String {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100_0000_01b3);
+    }
+    format!("{:016x}-{}", h, bytes.len())
+}
+
+/// Whether two files have the same contents.
+fn same_contents(a: &Path, b: &Path) -> bool {
+    match (a.metadata(), b.metadata()) {
+        (Ok(ma), Ok(mb)) if ma.len() == mb.len() => (),
+        _ => return false,
+    }
+    match (std::fs::read(a), std::fs::read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Save the contents of a file we are about to overwrite or remove
+/// under its hash.  A different file already saved under the same
+/// hash would make rolling back put back the wrong contents, so that
+/// is an error.
+fn save_object(objects: &Path, hash: &str, contents: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let path = objects.join(hash);
+    match std::fs::read(&path) {
+        Ok(saved) if saved == contents => Ok(()),
+        Ok(_) => Err(format!("two different files have the hash {}", hash).into()),
+        Err(_) => Ok(std::fs::write(path, contents)?),
+    }
+}
+
+fn relative(top: &Path, path: &Path) -> String {
+    path.strip_prefix(top).unwrap().to_string_lossy().replace('\\', "/")
+}
+
+/// Hash every file in a directory (other than our own bookkeeping).
+fn hash_dir(top: &Path) -> Result<Manifest, Box<dyn std::error::Error>> {
+    let mut manifest = Manifest::new();
+    for f in files_in(top) {
+        let name = relative(top, &f);
+        if name != ".build-state" {
+            manifest.insert(name, content_hash(&std::fs::read(&f)?));
+        }
+    }
+    Ok(manifest)
+}
+
+fn read_manifest(path: &Path) -> Option<Manifest> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+fn write_manifest(path: &Path, manifest: &Manifest) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::write(path, serde_json::to_string_pretty(manifest)?)?;
+    Ok(())
+}
+
+/// Make target hold exactly the files in a manifest, getting the
+/// contents of each changed file from `fetch`.  Anything we overwrite
+/// or remove is saved in `state` first, so the old state becomes the
+/// one we can roll back to.
+fn apply(target: &Path, state: &Path, wanted: &Manifest, fetch: impl Fn(&str, &str) -> Option<PathBuf>)
+         -> Result<Deployed, Box<dyn std::error::Error>>
+{
+    let objects = state.join("objects");
+    std::fs::create_dir_all(&objects)?;
+    let current = match read_manifest(&state.join("manifest.json")) {
+        Some(m) => m,
+        None => hash_dir(target)?,
+    };
+    let mut previous = Manifest::new();
+    let mut unchanged = BTreeSet::new();
+    let mut done = Deployed { copied: 0, removed: 0 };
+    for f in files_in(target) {
+        let name = relative(target, &f);
+        if let Some(hash) = current.get(&name).filter(|&h| wanted.get(&name) == Some(h)) {
+            // With nothing to fetch (rolling back a file the last deploy
+            // left alone), what is already there is the file we want.
+            let same = match fetch(&name, hash) {
+                Some(from) => same_contents(&from, &f),
+                None => true,
+            };
+            if same {
+                previous.insert(name.clone(), hash.clone());
+                unchanged.insert(name);
+                continue;
+            }
+        }
+        let contents = std::fs::read(&f)?;
+        let hash = content_hash(&contents);
+        save_object(&objects, &hash, &contents)?;
+        previous.insert(name.clone(), hash);
+        if !wanted.contains_key(&name) {
+            std::fs::remove_file(&f)?;
+            // This only succeeds once the directory is empty.
+            f.parent().map(std::fs::remove_dir);
+            done.removed += 1;
+        }
+    }
+    for (name, hash) in wanted.iter() {
+        let to = target.join(name);
+        if unchanged.contains(name) {
+            continue;
+        }
+        if let Some(dir) = to.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let from = fetch(name, hash).ok_or_else(|| format!("we no longer have {}", name))?;
+        std::fs::copy(from, &to)?;
+        done.copied += 1;
+    }
+    // We only keep what we need to put back the previous state.
+    for f in files_in(&objects) {
+        if !previous.values().any(|h| f.ends_with(h)) {
+            std::fs::remove_file(&f)?;
+        }
+    }
+    write_manifest(&state.join("previous.json"), &previous)?;
+    write_manifest(&state.join("manifest.json"), wanted)?;
+    Ok(done)
+}
+
+/// Copy the files of `source` that differ from what was last
+/// deployed into `target`, and remove whatever no longer belongs,
+/// keeping what we need to roll back in `state`.
+pub fn deploy(source: &Path, target: &Path, state: &Path) -> Result<Deployed, Box<dyn std::error::Error>> {
+    let wanted = hash_dir(source)?;
+    apply(target, state, &wanted, |name, _| Some(source.join(name)))
+}
+
+/// Put back whatever was in `target` before the last deploy (or
+/// rollback, so rolling back twice gets you where you started).
+pub fn rollback(target: &Path, state: &Path) -> Result<Deployed, Box<dyn std::error::Error>> {
+    let previous = read_manifest(&state.join("previous.json"))
+        .ok_or("there is no previous deploy to roll back to")?;
+    let objects = state.join("objects");
+    // The files we need are about to be cleaned out of objects, so
+    // we set them aside first.
+    let saved = tempfile::tempdir()?;
+    for hash in previous.values() {
+        let from = objects.join(hash);
+        if from.exists() {
+            std::fs::copy(&from, saved.path().join(hash))?;
+        }
+    }
+    apply(target, state, &previous, |_, hash| {
+        Some(saved.path().join(hash)).filter(|f| f.exists())
+    })
+}
+
+#[test]
+fn deploy_copies_changes_and_rolls_back() {
+    let source = tempfile::tempdir().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    let deploy = |source: &Path, target: &Path| deploy(source, target, state.path());
+    let rollback = |target: &Path| rollback(target, state.path());
+    let write = |dir: &Path, name: &str, contents: &str| {
+        std::fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+        std::fs::write(dir.join(name), contents).unwrap();
+    };
+    let read = |name: &str| std::fs::read_to_string(target.path().join(name)).ok();
+    write(target.path(), "old.html", "from before we deployed");
+    write(source.path(), "index.html", "home");
+    write(source.path(), "concept/1", "one");
+    write(source.path(), "concept/2", "two");
+
+    assert_eq!(deploy(source.path(), target.path()).unwrap(),
+               Deployed { copied: 3, removed: 1 });
+    assert_eq!(read("old.html"), None);
+    assert_eq!(deploy(source.path(), target.path()).unwrap(),
+               Deployed { copied: 0, removed: 0 });
+
+    write(source.path(), "concept/1", "uno");
+    std::fs::remove_file(source.path().join("concept/2")).unwrap();
+    assert_eq!(deploy(source.path(), target.path()).unwrap(),
+               Deployed { copied: 1, removed: 1 });
+    assert_eq!(read("concept/1").as_deref(), Some("uno"));
+    assert_eq!(read("concept/2"), None);
+
+    rollback(target.path()).unwrap();
+    assert_eq!(read("concept/1").as_deref(), Some("one"));
+    assert_eq!(read("concept/2").as_deref(), Some("two"));
+    assert_eq!(read("index.html").as_deref(), Some("home"));
+    rollback(target.path()).unwrap();
+    assert_eq!(read("concept/1").as_deref(), Some("uno"));
+    assert_eq!(read("concept/2"), None);
+    // Nothing of an old version ends up where it would be published.
+    assert_eq!(files_in(target.path()).len(), 2);
+
+    // A file changed behind our back is copied even though the
+    // manifest says it is up to date.
+    write(target.path(), "index.html", "edited by hand");
+    assert_eq!(deploy(source.path(), target.path()).unwrap(),
+               Deployed { copied: 1, removed: 0 });
+    assert_eq!(read("index.html").as_deref(), Some("home"));
+}
@@ -0,0 +1,229 @@
+use display_as::{format_as, HTML, URL};
+use crate::data::{absolute, representation_hue, Activity, ConceptID, CourseID, Data,
+                  RepresentationID};
+
+impl Data {
+    /// The first course with an activity that teaches a concept.
+    fn first_course_for(&self, id: ConceptID) -> Option<CourseID> {
+        self.courses()
+            .find(|c| c.activities.iter()
+                  .any(|&a| self.get(a).new_concepts.iter().any(|cr| cr.concept == id)))
+            .map(|c| c.id)
+    }
+    /// The concept graph for Graphviz, with the concepts grouped into
+    /// a cluster for the course that first teaches them, and each
+    /// node linking to its page.
+    pub fn concept_dot(&self, options: &DotOptions) -> String {
+        use std::collections::{BTreeMap, BTreeSet};
+        let edges = self.prereq_edges();
+        let first: BTreeMap<ConceptID, CourseID> = self.concepts.iter()
+            .filter_map(|c| self.first_course_for(c.id).map(|course| (c.id, course)))
+            .collect();
+        let shown_course = |c: CourseID| options.courses.as_ref().is_none_or(|cs| cs.contains(&c));
+        let mut concepts: BTreeSet<ConceptID> = if options.courses.is_some() {
+            first.iter().filter(|&(_, &course)| shown_course(course)).map(|(&c, _)| c).collect()
+        } else {
+            // Only include concepts that have *some* dependency.
+            edges.iter().flat_map(|&(a, b)| vec![a, b]).collect()
+        };
+        // Show the prerequisites of the concepts we show, even those
+        // taught in other courses.
+        let needed: Vec<ConceptID> = edges.iter()
+            .filter(|(_, b)| concepts.contains(b))
+            .map(|&(a, _)| a)
+            .collect();
+        concepts.extend(needed);
+        let activities: Vec<&Activity> = if options.activities {
+            self.activities.iter()
+                .filter(|a| a.new_concepts.iter().any(|cr| concepts.contains(&cr.concept)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let node = |id: ConceptID| {
+            let c = self.get(id);
+            let (fill, style) = match options.color {
+                DotColor::Status if !first.contains_key(&id) => ("#eeeeee".to_string(), "filled,dashed"),
+                DotColor::Status if c.long_description.len() == 0 => ("#fff5cc".to_string(), "filled"),
+                DotColor::Representation if !c.representations.is_empty() => {
+                    let r = *c.representations.keys().next().unwrap();
+                    (representation_dot_color(r), "filled")
+                }
+                _ => ("#ffffff".to_string(), "filled"),
+            };
+            format!("{} [label={}, URL={}, style=\"{}\", fillcolor=\"{}\"];\n",
+                    format_as!(HTML, id), dot_label(&c.name),
+                    dot_string(&absolute(format_as!(URL, id))), style, fill)
+        };
+        let activity_node = |a: &Activity| {
+            format!("{} [label={}, URL={}, shape=note, style=filled, fillcolor=\"#e8f0ff\"];\n",
+                    format_as!(HTML, a.id), dot_label(&a.name),
+                    dot_string(&absolute(format_as!(URL, a.id))))
+        };
+
+        let mut out = format!("digraph concept_map {{\n  rankdir={};\n  node [shape=box, fontname=\"sans-serif\"];\n",
+                              options.rankdir);
+        for course in self.courses().filter(|c| shown_course(c.id)) {
+            let inside: Vec<ConceptID> = concepts.iter().cloned()
+                .filter(|c| first.get(c) == Some(&course.id))
+                .collect();
+            let inside_activities: Vec<&Activity> = activities.iter().cloned()
+                .filter(|a| course.activities.contains(&a.id))
+                .collect();
+            if inside.is_empty() && inside_activities.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("  subgraph cluster_{} {{\n    label={};\n    URL={};\n",
+                                  format_as!(HTML, course.id), dot_label(&course.name),
+                                  dot_string(&absolute(format_as!(URL, course.id)))));
+            for c in inside {
+                out.push_str(&format!("    {}", node(c)));
+            }
+            for a in inside_activities {
+                out.push_str(&format!("    {}", activity_node(a)));
+            }
+            out.push_str("  }\n");
+        }
+        for &c in concepts.iter().filter(|c| first.get(c).is_none_or(|&course| !shown_course(course))) {
+            out.push_str(&format!("  {}", node(c)));
+        }
+        for a in activities.iter().filter(|a| !self.courses()
+                                          .any(|c| shown_course(c.id) && c.activities.contains(&a.id))) {
+            out.push_str(&format!("  {}", activity_node(a)));
+        }
+        for &(a, b) in edges.iter().filter(|(a, b)| concepts.contains(a) && concepts.contains(b)) {
+            out.push_str(&format!("  {} -> {};\n", format_as!(HTML, a), format_as!(HTML, b)));
+        }
+        for a in activities.iter() {
+            let prereqs: BTreeSet<ConceptID> = a.prereq_concepts.iter().map(|cr| cr.concept)
+                .filter(|c| concepts.contains(c)).collect();
+            let new: BTreeSet<ConceptID> = a.new_concepts.iter().map(|cr| cr.concept)
+                .filter(|c| concepts.contains(c)).collect();
+            for c in prereqs {
+                out.push_str(&format!("  {} -> {} [color=\"#99aacc\", style=dashed];\n",
+                                      format_as!(HTML, c), format_as!(HTML, a.id)));
+            }
+            for c in new {
+                out.push_str(&format!("  {} -> {} [color=\"#99aacc\", style=dashed];\n",
+                                      format_as!(HTML, a.id), format_as!(HTML, c)));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// The same color as `representation_color`, as Graphviz wants it.
+fn representation_dot_color(id: RepresentationID) -> String {
+    format!("{:.3} 0.174 0.964", representation_hue(id) as f64/360.0)
+}
+
+/// A string as a quoted Graphviz id.
+fn dot_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A name as a Graphviz label, with its math as plain text, since
+/// Graphviz can't typeset TeX.
+fn dot_label(name: &str) -> String {
+    let text: String = crate::markdown::split_math(name).into_iter()
+        .map(|span| match span {
+            crate::markdown::Span::Text(t) => t.to_string(),
+            crate::markdown::Span::Math(m) => crate::mathml::math_to_text(m),
+        })
+        .collect();
+    dot_string(&text)
+}
+
+/// What to put in the Graphviz concept map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DotOptions {
+    /// Which way the prerequisites point: TB, LR, BT or RL.
+    pub rankdir: String,
+    /// Whether to show the activities that teach the concepts.
+    pub activities: bool,
+    /// The courses whose concepts to show, or all of them.
+    pub courses: Option<Vec<CourseID>>,
+    pub color: DotColor,
+}
+/// How to color the concepts in the Graphviz concept map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotColor {
+    /// Gray if no course teaches the concept, and yellow if it needs
+    /// a description.
+    Status,
+    /// The color of its first representation.
+    Representation,
+}
+impl Default for DotOptions {
+    fn default() -> Self {
+        DotOptions {
+            rankdir: "TB".to_string(),
+            activities: false,
+            courses: None,
+            color: DotColor::Status,
+        }
+    }
+}
+impl DotOptions {
+    /// The options asked for in the query of a url, such as
+    /// `?rankdir=LR&activities=true&courses=0,2&color=representation`,
+    /// ignoring any we don't understand.
+    pub fn from_query(query: &std::collections::HashMap<String, String>) -> Self {
+        let mut options = DotOptions::default();
+        if let Some(r) = query.get("rankdir").map(|r| r.to_uppercase()) {
+            if ["TB", "LR", "BT", "RL"].contains(&r.as_str()) {
+                options.rankdir = r;
+            }
+        }
+        options.activities = query.get("activities")
+            .is_some_and(|a| a.is_empty() || a == "true" || a == "1" || a == "yes");
+        options.courses = query.get("courses").map(|cs| {
+            cs.split(',').filter_map(|c| c.trim().trim_start_matches('C').parse().ok()).collect()
+        });
+        if query.get("color").map(|c| c.as_str()) == Some("representation") {
+            options.color = DotColor::Representation;
+        }
+        options
+    }
+}
+
+#[test]
+fn dot_clusters_concepts_by_course() {
+    let data = Data::new();
+    let all = data.concept_dot(&DotOptions::default());
+    assert_eq!(all.matches(" -> ").count(), data.prereq_edges().len());
+    for label in all.split("[label=").skip(1) {
+        let label = &label[..label.find(", URL=").unwrap()];
+        assert!(!label.contains('$') && !label.contains("\\\\("), "{}", label);
+    }
+    for (a, b) in data.prereq_edges() {
+        for c in [a, b] {
+            assert_eq!(all.matches(&format!(" {} [label=", format_as!(HTML, c))).count(), 1);
+            if let Some(course) = data.first_course_for(c) {
+                let cluster = &all[all.find(&format!("cluster_{} ", format_as!(HTML, course))).unwrap()..];
+                let cluster = &cluster[..cluster.find("  }\n").unwrap()];
+                assert!(cluster.contains(&format!(" {} [label=", format_as!(HTML, c))));
+            }
+        }
+    }
+
+    let query = [("rankdir", "lr"), ("activities", ""), ("courses", "1")].iter()
+        .map(|&(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let one = data.concept_dot(&DotOptions::from_query(&query));
+    assert!(one.contains("rankdir=LR;"));
+    assert_eq!(one.matches("subgraph cluster_").count(), 1);
+    let course = data.get(CourseID(1));
+    // Urls are quoted for dot, not escaped for html.
+    assert!(one.contains(&format!("URL={};", dot_string(&absolute(format_as!(URL, CourseID(1)))))));
+    assert!(one.contains(&format!("subgraph cluster_{} {{\n    label={};",
+                                  format_as!(HTML, CourseID(1)), dot_label(&course.name))));
+    for &a in course.activities.iter()
+        .filter(|&&a| data.get(a).new_concepts.iter()
+                .any(|cr| data.first_course_for(cr.concept) == Some(CourseID(1))))
+    {
+        assert!(one.contains(&format!("    {} [label=", format_as!(HTML, a))));
+    }
+}
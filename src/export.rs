@@ -0,0 +1,211 @@
+//! Writing the concept graph in formats other tools can read:
+//! GraphML for graph analysis, JSON Graph Format for scripts, and
+//! Mermaid for embedding in slides and markdown.
+
+use display_as::{format_as, HTML};
+use serde_json::json;
+use crate::data::Data;
+
+/// The concepts and their prerequisites, with the names of
+/// everything another tool might want to know about them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConceptGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+/// A concept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphNode {
+    /// The id we use everywhere else, such as `c3`.
+    pub id: String,
+    pub name: String,
+    /// The courses that teach the concept.
+    pub courses: Vec<String>,
+    pub representations: Vec<String>,
+}
+
+/// A prerequisite, pointing from the prerequisite to the concept
+/// that needs it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    /// The activities that build the target on the source.
+    pub activities: Vec<String>,
+}
+
+impl ConceptGraph {
+    /// The graph as GraphML, with the names as data on the nodes and
+    /// edges.  Lists of names are separated by semicolons.
+    pub fn graphml(&self) -> String {
+        let mut out = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#, "\n",
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#, "\n",
+            r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#, "\n",
+            r#"  <key id="courses" for="node" attr.name="courses" attr.type="string"/>"#, "\n",
+            r#"  <key id="representations" for="node" attr.name="representations" attr.type="string"/>"#, "\n",
+            r#"  <key id="relation" for="edge" attr.name="relation" attr.type="string"/>"#, "\n",
+            r#"  <key id="activities" for="edge" attr.name="activities" attr.type="string"/>"#, "\n",
+            r#"  <graph id="concepts" edgedefault="directed">"#, "\n"));
+        for n in self.nodes.iter() {
+            out.push_str(&format!(
+                concat!(r#"    <node id="{}">"#, "\n",
+                        r#"      <data key="name">{}</data>"#, "\n",
+                        r#"      <data key="courses">{}</data>"#, "\n",
+                        r#"      <data key="representations">{}</data>"#, "\n",
+                        "    </node>\n"),
+                xml(&n.id), xml(&n.name), xml(&n.courses.join("; ")),
+                xml(&n.representations.join("; "))));
+        }
+        for e in self.edges.iter() {
+            out.push_str(&format!(
+                concat!(r#"    <edge source="{}" target="{}">"#, "\n",
+                        r#"      <data key="relation">prereq</data>"#, "\n",
+                        r#"      <data key="activities">{}</data>"#, "\n",
+                        "    </edge>\n"),
+                xml(&e.source), xml(&e.target), xml(&e.activities.join("; "))));
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    /// The graph in version 2 of the JSON Graph Format, with the
+    /// courses, representations and activities as metadata.
+    pub fn json_graph(&self) -> String {
+        let nodes: serde_json::Map<String, serde_json::Value> = self.nodes.iter()
+            .map(|n| (n.id.clone(), json!({
+                "label": n.name,
+                "metadata": {
+                    "courses": n.courses,
+                    "representations": n.representations,
+                },
+            })))
+            .collect();
+        let edges: Vec<_> = self.edges.iter()
+            .map(|e| json!({
+                "source": e.source,
+                "target": e.target,
+                "relation": "prereq",
+                "directed": true,
+                "metadata": { "activities": e.activities },
+            }))
+            .collect();
+        let graph = json!({
+            "graph": {
+                "id": "concepts",
+                "label": "Concept map",
+                "directed": true,
+                "nodes": nodes,
+                "edges": edges,
+            }
+        });
+        serde_json::to_string_pretty(&graph).expect("trouble generating json")
+    }
+
+    /// The graph as a Mermaid flowchart, with the courses and
+    /// representations in small print under each name, and each edge
+    /// labelled with its activities.
+    pub fn mermaid(&self) -> String {
+        let mut out = String::from("flowchart TD\n");
+        for n in self.nodes.iter() {
+            let details: Vec<String> = n.courses.iter().chain(n.representations.iter())
+                .map(|x| mermaid_text(x))
+                .collect();
+            if details.is_empty() {
+                out.push_str(&format!("  {}[\"{}\"]\n", n.id, mermaid_text(&n.name)));
+            } else {
+                out.push_str(&format!("  {}[\"{}<br/><small>{}</small>\"]\n",
+                                      n.id, mermaid_text(&n.name), details.join(", ")));
+            }
+        }
+        for e in self.edges.iter() {
+            if e.activities.is_empty() {
+                out.push_str(&format!("  {} --> {}\n", e.source, e.target));
+            } else {
+                let label: Vec<String> = e.activities.iter().map(|a| mermaid_text(a)).collect();
+                out.push_str(&format!("  {} -->|\"{}\"| {}\n", e.source, label.join(", "), e.target));
+            }
+        }
+        out
+    }
+}
+
+fn xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Text safe to put between quotes in a Mermaid label, which treats
+/// `#name;` as an entity.
+fn mermaid_text(s: &str) -> String {
+    s.replace('#', "#35;").replace('"', "#quot;").replace('<', "#lt;").replace('>', "#gt;")
+}
+
+impl Data {
+    /// Every concept and prerequisite, for exporting to other tools.
+    pub fn concept_graph(&self) -> ConceptGraph {
+        let nodes = self.concepts.iter().map(|c| GraphNode {
+            id: format_as!(HTML, c.id),
+            name: c.name.clone(),
+            courses: self.courses_for_concept(c.id).iter()
+                .map(|&course| self.get(course).name.clone())
+                .collect(),
+            representations: c.representations.keys()
+                .map(|&r| self.get(r).name.clone())
+                .collect(),
+        }).collect();
+        let edges = self.prereq_edges().iter().map(|&(a, b)| GraphEdge {
+            source: format_as!(HTML, a),
+            target: format_as!(HTML, b),
+            activities: self.activities.iter()
+                .filter(|x| x.prereq_concepts.iter().any(|cr| cr.concept == a)
+                        && x.new_concepts.iter().any(|cr| cr.concept == b))
+                .map(|x| x.name.clone())
+                .collect(),
+        }).collect();
+        ConceptGraph { nodes, edges }
+    }
+}
+
+#[test]
+fn exports_names_and_activities() {
+    let graph = ConceptGraph {
+        nodes: vec![
+            GraphNode {
+                id: "c0".to_string(),
+                name: "Energy".to_string(),
+                courses: vec!["Energy & Entropy".to_string()],
+                representations: vec!["Graph".to_string()],
+            },
+            GraphNode {
+                id: "c1".to_string(),
+                name: "Say \"heat\" <quietly>".to_string(),
+                courses: Vec::new(),
+                representations: Vec::new(),
+            },
+        ],
+        edges: vec![GraphEdge {
+            source: "c0".to_string(),
+            target: "c1".to_string(),
+            activities: vec!["Rubber band".to_string()],
+        }],
+    };
+
+    let graphml = graph.graphml();
+    assert_eq!(graphml.matches("<node ").count(), 2);
+    assert!(graphml.contains(r#"<data key="courses">Energy &amp; Entropy</data>"#));
+    assert!(graphml.contains(r#"<data key="name">Say &quot;heat&quot; &lt;quietly&gt;</data>"#));
+    assert!(graphml.contains(r#"<edge source="c0" target="c1">"#));
+
+    let json: serde_json::Value = serde_json::from_str(&graph.json_graph()).unwrap();
+    assert_eq!(json["graph"]["nodes"]["c0"]["label"], "Energy");
+    assert_eq!(json["graph"]["nodes"]["c0"]["metadata"]["representations"][0], "Graph");
+    assert_eq!(json["graph"]["edges"][0]["metadata"]["activities"][0], "Rubber band");
+
+    let mermaid = graph.mermaid();
+    assert!(mermaid.starts_with("flowchart TD\n"));
+    assert!(mermaid.contains(r#"c0["Energy<br/><small>Energy & Entropy, Graph</small>"]"#));
+    assert!(mermaid.contains(r#"c1["Say #quot;heat#quot; #lt;quietly#gt;"]"#));
+    assert!(mermaid.contains(r#"c0 -->|"Rubber band"| c1"#));
+}
+
@@ -0,0 +1,768 @@
+//! Laying out a directed graph in layers, as in the concept map.
+//!
+//! This is the usual Sugiyama pipeline: we break any cycles, assign
+//! nodes to layers so that no layer is wider than we ask and edges
+//! are short, add dummy nodes wherever an edge passes over a layer,
+//! order each layer to reduce crossings (barycenter sweeps followed by
+//! moving single slots), and finally give each node a horizontal
+//! position.  We lay the graph out both downwards and upwards, and
+//! keep whichever crosses less.  The ordering can start from the
+//! order of a previous layout, and keeps it unless that crosses more.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A place in a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Slot<N> {
+    /// A node of the graph.
+    Node(N),
+    /// A dummy node carrying the edge (from, to) through a layer
+    /// that it passes over.
+    Dummy(N, N),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layout<N> {
+    /// Each layer from the top, in order from left to right, with the
+    /// horizontal position of each slot.  Neighboring nodes are at
+    /// least one apart, and dummies half that.
+    pub layers: Vec<Vec<(Slot<N>, f64)>>,
+    /// The edges as drawn, each going down.
+    pub edges: Vec<(N, N)>,
+    /// The edges we drew upside down, to break cycles.
+    pub reversed: Vec<(N, N)>,
+}
+
+impl<N: Copy> Slot<N> {
+    /// The same slot in the graph with every edge reversed.
+    fn flipped(&self) -> Self {
+        match *self {
+            Slot::Node(n) => Slot::Node(n),
+            Slot::Dummy(a, b) => Slot::Dummy(b, a),
+        }
+    }
+}
+
+impl<N: Copy + Ord> Layout<N> {
+    /// The same layout upside down, for the graph with every edge
+    /// reversed.
+    fn flipped(self) -> Self {
+        Layout {
+            layers: self.layers.into_iter().rev()
+                .map(|l| l.into_iter().map(|(s, x)| (s.flipped(), x)).collect())
+                .collect(),
+            edges: self.edges.into_iter().map(|(a, b)| (b, a)).collect(),
+            reversed: self.reversed.into_iter().map(|(a, b)| (b, a)).collect(),
+        }
+    }
+    /// The slots of each layer, without their positions.
+    pub fn slots(&self) -> Vec<Vec<Slot<N>>> {
+        self.layers.iter().map(|l| l.iter().map(|&(s, _)| s).collect()).collect()
+    }
+    /// The slots that a slot connects to in the layer below it.
+    pub fn children(&self, layer: usize, slot: Slot<N>) -> Vec<Slot<N>> {
+        let graph = Graph::new(&self.edges);
+        match self.layers.get(layer + 1) {
+            Some(below) => below.iter()
+                .map(|&(s, _)| s)
+                .filter(|&s| graph.is_edge(slot, s))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+    /// The number of pairs of edges that cross.
+    pub fn crossings(&self) -> usize {
+        crossings(&self.slots(), &Graph::new(&self.edges))
+    }
+    /// Leave out the nodes that no longer exist, along with their
+    /// edges and any layers left empty.
+    pub fn retain(&mut self, keep: impl Fn(N) -> bool) {
+        let slot_kept = |s: &Slot<N>| match *s {
+            Slot::Node(n) => keep(n),
+            Slot::Dummy(a, b) => keep(a) && keep(b),
+        };
+        for layer in self.layers.iter_mut() {
+            layer.retain(|(s, _)| slot_kept(s));
+        }
+        self.layers.retain(|l| !l.is_empty());
+        self.edges.retain(|&(a, b)| keep(a) && keep(b));
+        self.reversed.retain(|&(a, b)| keep(a) && keep(b));
+    }
+    /// Leave out some edges of the graph (as given, not as drawn),
+    /// along with their dummies, without moving anything else.
+    pub fn remove_edges(&mut self, edges: &[(N, N)]) {
+        let drawn: Vec<(N, N)> = edges.iter()
+            .map(|&(a, b)| if self.reversed.contains(&(a, b)) { (b, a) } else { (a, b) })
+            .collect();
+        for layer in self.layers.iter_mut() {
+            layer.retain(|(s, _)| match *s {
+                Slot::Node(_) => true,
+                Slot::Dummy(a, b) => !drawn.contains(&(a, b)),
+            });
+        }
+        self.layers.retain(|l| !l.is_empty());
+        self.edges.retain(|e| !drawn.contains(e));
+        self.reversed.retain(|e| !edges.contains(e));
+    }
+    /// Add layers at the bottom holding nodes that have no edges, no
+    /// more than `width` to a layer.
+    pub fn add_rows(&mut self, nodes: &[N], width: usize) {
+        for row in nodes.chunks(width.max(1)) {
+            self.layers.push(row.iter().enumerate()
+                             .map(|(i, &n)| (Slot::Node(n), i as f64))
+                             .collect());
+        }
+    }
+    /// The number of real nodes in the widest layer.
+    pub fn width(&self) -> usize {
+        self.layers.iter()
+            .map(|l| l.iter().filter(|(s, _)| matches!(s, Slot::Node(_))).count())
+            .max().unwrap_or(0)
+    }
+}
+
+/// Lay out a graph given by its edges, with no more than `max_width`
+/// nodes in any layer.  Each layer starts out in the order it had in
+/// `previous` (which may be empty), so a graph that changes a little
+/// gets a layout that changes a little.
+pub fn layered<N: Copy + Ord>(edges: &[(N, N)], max_width: usize, previous: &[Vec<Slot<N>>])
+                              -> Layout<N>
+{
+    let down = layered_down(edges, max_width, Some(previous));
+    // Which layers we get depends on which end we start from, so we
+    // also try laying the graph out upside down.
+    let flipped: Vec<(N, N)> = edges.iter().map(|&(a, b)| (b, a)).collect();
+    let previous: Vec<Vec<Slot<N>>> = previous.iter().rev()
+        .map(|l| l.iter().map(|s| s.flipped()).collect())
+        .collect();
+    let up = layered_down(&flipped, max_width, Some(&previous)).flipped();
+    if up.crossings() < down.crossings() {
+        up
+    } else {
+        down
+    }
+}
+
+/// Like `layered`, but a narrower layering sometimes untangles where
+/// the widest one can't, at the cost of a layer or so.  So if there
+/// are crossings we also try down to half the width (but never a
+/// single column), and keep a narrower layout only if it has fewer
+/// crossings.  This takes a few times as long.
+pub fn untangled<N: Copy + Ord>(edges: &[(N, N)], max_width: usize, previous: &[Vec<Slot<N>>])
+                                -> Layout<N>
+{
+    let mut best = layered(edges, max_width, previous);
+    for width in ((max_width/2).max(2)..max_width).rev() {
+        if best.crossings() == 0 {
+            break;
+        }
+        let other = layered(edges, width, previous);
+        if other.crossings() < best.crossings() {
+            best = other;
+        }
+    }
+    best
+}
+
+/// Lay out a graph in layers as `layered` does, but leave each layer
+/// in whatever order it comes, which is much quicker.  This is for
+/// drawing something while the real layout is worked out.
+pub fn unordered<N: Copy + Ord>(edges: &[(N, N)], max_width: usize) -> Layout<N> {
+    layered_down(edges, max_width, None)
+}
+
+/// Lay out a graph downwards, ordering the layers starting from
+/// `previous` unless there is none to give.
+fn layered_down<N: Copy + Ord>(edges: &[(N, N)], max_width: usize, previous: Option<&[Vec<Slot<N>>]>)
+                               -> Layout<N>
+{
+    let (edges, reversed) = break_cycles(edges);
+    let graph = Graph::new(&edges);
+    let (layer, number) = assign_layers(&graph, max_width.max(1));
+    let layers = with_dummies(&edges, &layer, &number);
+    positioned(layers, &graph, previous, edges, reversed)
+}
+
+/// Order the layers (if we have a `previous` to start from) and give
+/// each slot its position.
+fn positioned<N: Copy + Ord>(mut layers: Vec<Vec<Slot<N>>>, graph: &Graph<N>,
+                             previous: Option<&[Vec<Slot<N>>]>,
+                             edges: Edges<N>, reversed: Edges<N>) -> Layout<N>
+{
+    if let Some(previous) = previous {
+        order(&mut layers, graph, previous);
+    }
+    let x = coordinates(&layers, graph);
+    Layout {
+        layers: layers.into_iter().zip(x)
+            .map(|(l, x)| l.into_iter().zip(x).collect())
+            .collect(),
+        edges,
+        reversed,
+    }
+}
+
+/// Lay out a graph with each node in a layer of our choosing, which
+/// must number the layers from the top with none left empty.  Any
+/// edge that goes up is drawn reversed, edges within a layer are left
+/// out, as are edges to nodes that have no layer.
+pub fn in_layers<N: Copy + Ord>(edges: &[(N, N)], layer: &BTreeMap<N, usize>,
+                                previous: &[Vec<Slot<N>>]) -> Layout<N>
+{
+    in_layers_ordering(edges, layer, Some(previous))
+}
+
+/// Like `in_layers`, but as with `unordered` we leave each layer in
+/// whatever order it comes.
+pub fn in_layers_unordered<N: Copy + Ord>(edges: &[(N, N)], layer: &BTreeMap<N, usize>) -> Layout<N> {
+    in_layers_ordering(edges, layer, None)
+}
+
+fn in_layers_ordering<N: Copy + Ord>(edges: &[(N, N)], layer: &BTreeMap<N, usize>,
+                                     previous: Option<&[Vec<Slot<N>>]>) -> Layout<N>
+{
+    let mut drawn = Vec::new();
+    let mut reversed = Vec::new();
+    for &(a, b) in edges {
+        match (layer.get(&a), layer.get(&b)) {
+            (Some(la), Some(lb)) if la < lb => drawn.push((a, b)),
+            (Some(la), Some(lb)) if la > lb => {
+                drawn.push((b, a));
+                reversed.push((a, b));
+            }
+            _ => (),
+        }
+    }
+    drawn.sort();
+    drawn.dedup();
+    reversed.sort();
+    reversed.dedup();
+    let graph = Graph::new(&drawn);
+    let number = layer.keys().enumerate().map(|(i, &n)| (n, i)).collect();
+    let layers = with_dummies(&drawn, layer, &number);
+    positioned(layers, &graph, previous, drawn, reversed)
+}
+
+type Edges<N> = Vec<(N, N)>;
+
+/// Reverse edges until there are no cycles, returning the edges (with
+/// any duplicates and loops dropped) and those we reversed.
+fn break_cycles<N: Copy + Ord>(edges: &[(N, N)]) -> (Edges<N>, Edges<N>) {
+    let mut children: BTreeMap<N, BTreeSet<N>> = BTreeMap::new();
+    for &(a, b) in edges.iter().filter(|(a, b)| a != b) {
+        children.entry(a).or_default().insert(b);
+        children.entry(b).or_default();
+    }
+    // A depth-first search, in which an edge leading back to a node
+    // we are still exploring closes a cycle.
+    let mut done = BTreeSet::new();
+    let mut reversed = BTreeSet::new();
+    for &root in children.keys() {
+        if done.contains(&root) {
+            continue;
+        }
+        let mut on_stack: BTreeSet<N> = std::iter::once(root).collect();
+        let mut stack = vec![(root, children[&root].iter().rev().cloned().collect::<Vec<_>>())];
+        while let Some((node, todo)) = stack.last_mut() {
+            let node = *node;
+            match todo.pop() {
+                Some(next) if on_stack.contains(&next) => {
+                    reversed.insert((node, next));
+                }
+                Some(next) if !done.contains(&next) => {
+                    on_stack.insert(next);
+                    stack.push((next, children[&next].iter().rev().cloned().collect()));
+                }
+                Some(_) => (),
+                None => {
+                    on_stack.remove(&node);
+                    done.insert(node);
+                    stack.pop();
+                }
+            }
+        }
+    }
+    let mut out: Vec<(N, N)> = children.iter()
+        .flat_map(|(&a, bs)| bs.iter().map(move |&b| (a, b)))
+        .map(|(a, b)| if reversed.contains(&(a, b)) { (b, a) } else { (a, b) })
+        .collect();
+    out.sort();
+    out.dedup();
+    (out, reversed.into_iter().collect())
+}
+
+/// The parents and children of each node in a graph without cycles.
+struct Graph<N> {
+    parents: BTreeMap<N, Vec<N>>,
+    children: BTreeMap<N, Vec<N>>,
+}
+
+impl<N: Copy + Ord> Graph<N> {
+    fn new(edges: &[(N, N)]) -> Self {
+        let mut parents: BTreeMap<N, Vec<N>> = BTreeMap::new();
+        let mut children: BTreeMap<N, Vec<N>> = BTreeMap::new();
+        for &(a, b) in edges {
+            parents.entry(a).or_default();
+            children.entry(b).or_default();
+            parents.entry(b).or_default().push(a);
+            children.entry(a).or_default().push(b);
+        }
+        Graph { parents, children }
+    }
+    /// Whether there is an edge from a slot to one in the next layer.
+    fn is_edge(&self, upper: Slot<N>, lower: Slot<N>) -> bool {
+        match (upper, lower) {
+            (Slot::Node(a), Slot::Node(b)) => self.children.get(&a).is_some_and(|c| c.contains(&b)),
+            (Slot::Node(a), Slot::Dummy(b, _)) => a == b,
+            (Slot::Dummy(a, b), Slot::Dummy(c, d)) => (a, b) == (c, d),
+            (Slot::Dummy(_, b), Slot::Node(c)) => b == c,
+        }
+    }
+    /// The edges between two neighboring layers, as pairs of indices.
+    fn edges_between(&self, upper: &[Slot<N>], lower: &[Slot<N>]) -> Vec<(usize, usize)> {
+        let index: BTreeMap<Slot<N>, usize> = lower.iter().enumerate().map(|(j, &s)| (s, j)).collect();
+        let mut out = Vec::new();
+        for (i, &u) in upper.iter().enumerate() {
+            match u {
+                Slot::Node(a) => {
+                    for &b in self.children.get(&a).into_iter().flatten() {
+                        if let Some(&j) = index.get(&Slot::Node(b)).or_else(|| index.get(&Slot::Dummy(a, b))) {
+                            out.push((i, j));
+                        }
+                    }
+                }
+                Slot::Dummy(a, b) => {
+                    if let Some(&j) = index.get(&Slot::Dummy(a, b)).or_else(|| index.get(&Slot::Node(b))) {
+                        out.push((i, j));
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Assign each node a layer, counting from the top, so that every
+/// edge goes down and no layer has more than `width` nodes.
+///
+/// We take the nodes depth first, putting each in the first layer
+/// below its parents with room, and then move
+/// nodes up or down wherever that shortens their edges, since every
+/// layer an edge passes over costs a dummy.
+fn assign_layers<N: Copy + Ord>(graph: &Graph<N>, width: usize) -> (BTreeMap<N, usize>, BTreeMap<N, usize>) {
+    // First we number the nodes, each after all its parents, choosing
+    // the node whose parents were numbered earliest.
+    let mut number: BTreeMap<N, usize> = BTreeMap::new();
+    let mut by_number = Vec::new();
+    while number.len() < graph.parents.len() {
+        let next = graph.parents.iter()
+            .filter(|(n, ps)| !number.contains_key(n) && ps.iter().all(|p| number.contains_key(p)))
+            .map(|(&n, ps)| {
+                let mut ns: Vec<usize> = ps.iter().map(|p| number[p]).collect();
+                ns.sort_unstable_by(|a, b| b.cmp(a));
+                (ns, n)
+            })
+            .max()
+            .expect("a graph without cycles always has a node to number")
+            .1;
+        number.insert(next, number.len());
+        by_number.push(next);
+    }
+    let mut layer = BTreeMap::new();
+    let mut sizes: Vec<usize> = Vec::new();
+    for &n in by_number.iter() {
+        let mut l = graph.parents[&n].iter().map(|p| layer[p] + 1).max().unwrap_or(0)
+            .max(sizes.len().saturating_sub(2));
+        while sizes.get(l).map(|&s| s >= width).unwrap_or(false) {
+            l += 1;
+        }
+        if sizes.len() <= l {
+            sizes.resize(l + 1, 0);
+        }
+        sizes[l] += 1;
+        layer.insert(n, l);
+    }
+    // The total length of a node's edges changes by (parents -
+    // children) for each layer it moves down.
+    let mut moved = true;
+    while moved {
+        moved = false;
+        for &n in by_number.iter() {
+            let parents = graph.parents[&n].len();
+            let children = graph.children[&n].len();
+            let here = layer[&n];
+            let highest = graph.parents[&n].iter().map(|p| layer[p] + 1).max().unwrap_or(0);
+            let lowest = graph.children[&n].iter().map(|c| layer[c] - 1).min()
+                .unwrap_or(sizes.len() - 1);
+            let to = if parents > children {
+                (highest..here).find(|&l| sizes[l] < width)
+            } else if children > parents {
+                (here + 1..=lowest).rev().find(|&l| sizes[l] < width)
+            } else {
+                None
+            };
+            if let Some(to) = to {
+                sizes[here] -= 1;
+                sizes[to] += 1;
+                layer.insert(n, to);
+                moved = true;
+            }
+        }
+    }
+    // Finally we close up any layers we emptied.
+    let mut renumber = Vec::new();
+    let mut used = 0;
+    for &s in sizes.iter() {
+        renumber.push(used);
+        if s > 0 {
+            used += 1;
+        }
+    }
+    (layer.into_iter().map(|(n, l)| (n, renumber[l])).collect(), number)
+}
+
+/// The layers with a dummy in every layer an edge passes over.
+fn with_dummies<N: Copy + Ord>(edges: &[(N, N)], layer: &BTreeMap<N, usize>, number: &BTreeMap<N, usize>) -> Vec<Vec<Slot<N>>> {
+    let mut layers = vec![Vec::new(); layer.values().max().map(|l| l + 1).unwrap_or(0)];
+    for (&n, &l) in layer.iter() {
+        layers[l].push(Slot::Node(n));
+    }
+    for &(a, b) in edges {
+        for l in &mut layers[layer[&a] + 1..layer[&b]] {
+            l.push(Slot::Dummy(a, b));
+        }
+    }
+    for l in layers.iter_mut() {
+        l.sort_by_key(|s| match *s {
+            Slot::Node(n) => (number[&n], 0),
+            Slot::Dummy(a, b) => (number[&b], number[&a] + 1),
+        });
+    }
+    layers
+}
+
+/// The number of pairs of edges that cross between the layers.
+fn crossings<N: Copy + Ord>(layers: &[Vec<Slot<N>>], graph: &Graph<N>) -> usize {
+    layers.windows(2).map(|w| {
+        let mut ends = graph.edges_between(&w[0], &w[1]);
+        ends.sort_unstable();
+        let mut count = 0;
+        for (k, &(_, j)) in ends.iter().enumerate() {
+            count += ends[k+1..].iter().filter(|&&(_, j2)| j2 < j).count();
+        }
+        count
+    }).sum()
+}
+
+/// The median position of a slot's neighbors in another layer, if it
+/// has any.
+fn median(positions: &[f64]) -> Option<f64> {
+    let n = positions.len();
+    if n == 0 {
+        None
+    } else if n % 2 == 1 {
+        Some(positions[n/2])
+    } else {
+        Some(0.5*(positions[n/2 - 1] + positions[n/2]))
+    }
+}
+
+/// The mean of some positions, if there are any.
+fn mean(positions: &[f64]) -> Option<f64> {
+    if positions.is_empty() {
+        None
+    } else {
+        Some(positions.iter().sum::<f64>()/positions.len() as f64)
+    }
+}
+
+/// Order the slots in each layer to reduce crossings.
+fn order<N: Copy + Ord>(layers: &mut Vec<Vec<Slot<N>>>, graph: &Graph<N>,
+                        previous: &[Vec<Slot<N>>])
+{
+    // Start from the previous layout where we can.  A node keeps its
+    // place even if it moved to another layer, but the dummies of an
+    // edge are in many layers, so we only look for them in the same
+    // layer.
+    let mut was = BTreeMap::new();
+    for (l, layer) in previous.iter().enumerate() {
+        for (i, &s) in layer.iter().enumerate() {
+            let l = if let Slot::Dummy(..) = s { Some(l) } else { None };
+            was.insert((l, s), (i as f64 + 0.5)/layer.len() as f64);
+        }
+    }
+    for (l, layer) in layers.iter_mut().enumerate() {
+        let len = layer.len() as f64;
+        let mut keyed: Vec<(f64, Slot<N>)> = layer.iter().enumerate()
+            .map(|(i, &s)| {
+                let key = if let Slot::Dummy(..) = s { (Some(l), s) } else { (None, s) };
+                (was.get(&key).cloned().unwrap_or((i as f64 + 0.5)/len), s)
+            })
+            .collect();
+        keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        *layer = keyed.into_iter().map(|(_, s)| s).collect();
+    }
+
+    let (mut best, mut fewest) = sweep(layers.clone(), graph, true);
+    // Sweeping can get stuck, so we also try starting upwards, and
+    // from the mirror image.
+    let mirrored: Vec<Vec<Slot<N>>> = layers.iter().map(|l| l.iter().rev().cloned().collect()).collect();
+    for (start, down) in [(layers.clone(), false), (mirrored.clone(), true), (mirrored, false)] {
+        let (other, c) = sweep(start, graph, down);
+        if c < fewest {
+            best = other;
+            fewest = c;
+        }
+    }
+    *layers = best;
+}
+
+/// Sweep down and up the layers, sorting each by the positions of its
+/// neighbors in the last layer we sorted, until that stops helping.
+/// This gives the best order we found and its crossings.
+fn sweep<N: Copy + Ord>(mut layers: Vec<Vec<Slot<N>>>, graph: &Graph<N>, mut down: bool)
+                        -> (Vec<Vec<Slot<N>>>, usize)
+{
+    let mut best = layers.clone();
+    let mut fewest = crossings(&layers, graph);
+    let mut since_best = 0;
+    for _ in 0..64 {
+        if fewest == 0 || since_best > 8 {
+            break;
+        }
+        if down {
+            for l in 1..layers.len() {
+                let (above, below) = layers.split_at_mut(l);
+                sort_by_neighbors(&mut below[0], &above[l-1], graph);
+            }
+        } else {
+            for l in (0..layers.len().saturating_sub(1)).rev() {
+                let (above, below) = layers.split_at_mut(l + 1);
+                sort_by_neighbors(&mut above[l], &below[0], graph);
+            }
+        }
+        down = !down;
+        sift(&mut layers, graph);
+        let c = crossings(&layers, graph);
+        since_best += 1;
+        if c < fewest {
+            fewest = c;
+            best = layers.clone();
+            since_best = 0;
+        }
+    }
+    (best, fewest)
+}
+
+/// Sort a layer by the mean position of each slot's neighbors in
+/// a fixed layer next to it.  Slots with no neighbors there stay put.
+fn sort_by_neighbors<N: Copy + Ord>(layer: &mut Vec<Slot<N>>, fixed: &[Slot<N>], graph: &Graph<N>) {
+    let mut keyed: Vec<(f64, Slot<N>)> = layer.iter().enumerate()
+        .map(|(i, &s)| {
+            let positions: Vec<f64> = fixed.iter().enumerate()
+                .filter(|&(_, &f)| graph.is_edge(f, s) || graph.is_edge(s, f))
+                .map(|(j, _)| j as f64 * layer.len() as f64 / fixed.len() as f64)
+                .collect();
+            (mean(&positions).unwrap_or(i as f64), s)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    *layer = keyed.into_iter().map(|(_, s)| s).collect();
+}
+
+/// Move each slot to wherever in its layer gives the fewest
+/// crossings, as long as that removes any.
+fn sift<N: Copy + Ord>(layers: &mut [Vec<Slot<N>>], graph: &Graph<N>) {
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for l in 0..layers.len() {
+            // The positions of each slot's neighbors in the layers
+            // above and below.
+            let mut neighbors = vec![(Vec::new(), Vec::new()); layers[l].len()];
+            if l > 0 {
+                for (i, j) in graph.edges_between(&layers[l-1], &layers[l]) {
+                    neighbors[j].0.push(i);
+                }
+            }
+            if l + 1 < layers.len() {
+                for (i, j) in graph.edges_between(&layers[l], &layers[l+1]) {
+                    neighbors[i].1.push(j);
+                }
+            }
+            // Which slots are where in the layer doesn't change how
+            // many times the edges of two slots cross, only which of
+            // them is on the left.
+            let pairs = |a: &[usize], b: &[usize]| {
+                a.iter().map(|x| b.iter().filter(|&y| y < x).count()).sum::<usize>()
+            };
+            let cross: Vec<Vec<isize>> = neighbors.iter()
+                .map(|(above, below)| neighbors.iter()
+                     .map(|(a, b)| (pairs(above, a) + pairs(below, b)) as isize)
+                     .collect())
+                .collect();
+            let mut order: Vec<usize> = (0..cross.len()).collect();
+            for (u, right_of_u) in cross.iter().enumerate() {
+                let from = order.iter().position(|&x| x == u).unwrap();
+                order.remove(from);
+                // The crossings involving u when it is placed at each
+                // position, starting at the far left.
+                let mut here: isize = order.iter().map(|&v| right_of_u[v]).sum();
+                let mut costs = vec![here];
+                for &v in order.iter() {
+                    here += cross[v][u] - right_of_u[v];
+                    costs.push(here);
+                }
+                let mut best = from;
+                for (to, &c) in costs.iter().enumerate() {
+                    if c < costs[best] {
+                        best = to;
+                    }
+                }
+                if best != from {
+                    improved = true;
+                }
+                order.insert(best, u);
+            }
+            let layer = layers[l].clone();
+            layers[l] = order.into_iter().map(|i| layer[i]).collect();
+        }
+    }
+}
+
+/// The space we leave between two neighboring slots.
+fn separation<N>(a: Slot<N>, b: Slot<N>) -> f64 {
+    match (a, b) {
+        (Slot::Node(_), Slot::Node(_)) => 1.0,
+        _ => 0.5,
+    }
+}
+
+/// Give each slot a horizontal position, keeping the order of each
+/// layer, pulling each slot towards the median of its neighbors so
+/// long edges run straight down.
+fn coordinates<N: Copy + Ord>(layers: &[Vec<Slot<N>>], graph: &Graph<N>) -> Vec<Vec<f64>> {
+    let mut x: Vec<Vec<f64>> = layers.iter()
+        .map(|layer| {
+            let mut x = 0.0;
+            let mut out = Vec::new();
+            for (i, &s) in layer.iter().enumerate() {
+                if i > 0 {
+                    x += separation(layer[i-1], s);
+                }
+                out.push(x);
+            }
+            out
+        })
+        .collect();
+    for sweep in 0..8 {
+        let ls: Vec<usize> = if sweep % 2 == 0 {
+            (1..layers.len()).collect()
+        } else {
+            (0..layers.len().saturating_sub(1)).rev().collect()
+        };
+        for l in ls {
+            let other = if sweep % 2 == 0 { l - 1 } else { l + 1 };
+            let wanted: Vec<f64> = layers[l].iter().enumerate()
+                .map(|(i, &s)| {
+                    let mut positions: Vec<f64> = layers[other].iter().enumerate()
+                        .filter(|&(_, &o)| graph.is_edge(o, s) || graph.is_edge(s, o))
+                        .map(|(j, _)| x[other][j])
+                        .collect();
+                    positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    median(&positions).unwrap_or(x[l][i])
+                })
+                .collect();
+            x[l] = place(&layers[l], &wanted);
+        }
+    }
+    let left = x.iter().flatten().cloned().fold(f64::INFINITY, f64::min);
+    for x in x.iter_mut().flatten() {
+        *x -= left;
+    }
+    x
+}
+
+/// Positions as close as we can get to those wanted, while keeping
+/// the slots in order and apart.  We pack the slots once from each
+/// side and take the average, which keeps them just as far apart.
+fn place<N: Copy>(layer: &[Slot<N>], wanted: &[f64]) -> Vec<f64> {
+    let n = layer.len();
+    let mut from_left = wanted.to_vec();
+    for i in 1..n {
+        from_left[i] = from_left[i].max(from_left[i-1] + separation(layer[i-1], layer[i]));
+    }
+    let mut from_right = wanted.to_vec();
+    for i in (0..n.saturating_sub(1)).rev() {
+        from_right[i] = from_right[i].min(from_right[i+1] - separation(layer[i], layer[i+1]));
+    }
+    from_left.into_iter().zip(from_right).map(|(a, b)| 0.5*(a + b)).collect()
+}
+
+#[test]
+fn layout_untangles_and_respects_width() {
+    // Two chains drawn crossed, a long edge, and a cycle.
+    let edges = [(1, 4), (2, 3), (3, 5), (4, 6), (1, 6), (6, 7), (7, 8), (8, 6)];
+    let layout = layered(&edges, 2, &[]);
+    assert_eq!(layout.crossings(), 0);
+    assert!(layout.width() <= 2);
+    assert_eq!(layout.reversed.len(), 1);
+    let level = |n| layout.layers.iter().position(|l| l.iter().any(|&(s, _)| s == Slot::Node(n))).unwrap();
+    for &(a, b) in layout.edges.iter() {
+        assert!(level(a) < level(b), "{} is not above {}", a, b);
+        for l in level(a) + 1..level(b) {
+            assert!(layout.layers[l].iter().any(|&(s, _)| s == Slot::Dummy(a, b)));
+        }
+    }
+    for layer in layout.layers.iter() {
+        for w in layer.windows(2) {
+            assert!(w[1].1 - w[0].1 >= separation(w[0].0, w[1].0) - 1e-9);
+        }
+    }
+    assert_eq!(layered(&edges, 1, &[]).width(), 1);
+}
+
+#[test]
+fn layout_starts_from_previous() {
+    // Nothing to choose between here but the order of the layers.
+    let edges = [(1, 3), (2, 4)];
+    let flipped = vec![vec![Slot::Node(2), Slot::Node(1)], vec![Slot::Node(4), Slot::Node(3)]];
+    assert_eq!(layered(&edges, 4, &flipped).slots(), flipped);
+    let layout = layered(&edges, 4, &[]);
+    assert_eq!(layered(&edges, 4, &layout.slots()).slots(), layout.slots());
+}
+
+#[test]
+fn layout_keeps_its_order_through_a_small_edit() {
+    let flipped = layered(&[(1, 3), (2, 4)], 4,
+                          &[vec![Slot::Node(2), Slot::Node(1)], vec![Slot::Node(4), Slot::Node(3)]]);
+    // Adding a concept leaves the ones we had in the order they were.
+    let grown = layered(&[(1, 3), (2, 4), (5, 6)], 4, &flipped.slots());
+    let at = |n| grown.layers.iter().flatten().find(|&&(s, _)| s == Slot::Node(n)).unwrap().1;
+    assert!(at(2) < at(1));
+    assert!(at(4) < at(3));
+}
+
+
+#[test]
+fn remove_edges_leaves_the_rest_in_place() {
+    let mut layout = layered(&[(1, 2), (2, 3), (1, 3), (4, 5)], 4, &[]);
+    let before = layout.clone();
+    layout.remove_edges(&[(1, 3)]);
+    assert_eq!(layout.edges.len(), 3);
+    assert!(layout.layers.iter().flatten().all(|(s, _)| !matches!(s, Slot::Dummy(..))));
+    let nodes = |l: &Layout<i32>| -> Vec<(Slot<i32>, f64)> {
+        l.layers.iter().flatten().cloned().filter(|(s, _)| matches!(s, Slot::Node(_))).collect()
+    };
+    assert_eq!(nodes(&layout), nodes(&before));
+}
+
+#[test]
+fn retain_drops_nodes_and_their_edges() {
+    let mut layout = layered(&[(1, 2), (2, 3), (1, 3)], 4, &[]);
+    layout.retain(|n| n != 2);
+    assert_eq!(layout.edges, vec![(1, 3)]);
+    assert_eq!(layout.slots(), vec![vec![Slot::Node(1)], vec![Slot::Dummy(1, 3)],
+                                    vec![Slot::Node(3)]]);
+}
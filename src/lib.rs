@@ -2,6 +2,20 @@ pub mod data;
 pub mod activity;
 pub mod concept;
 pub mod representation;
+pub mod mathml;
+pub mod report;
+pub mod mirror;
+pub mod deploy;
+pub mod layout;
+pub mod svg;
+pub mod export;
+pub mod path;
+pub mod coverage;
+pub mod mentions;
+pub mod order;
+pub mod dot;
+pub mod maps;
 
 mod markdown;
+mod sanitize;
 mod atomicfile;
@@ -0,0 +1,607 @@
+//! The concept maps: the whole one as we serve it, the map around a
+//! concept, the map of concept-representations and the map of a
+//! course, along with the saved layouts of them all.
+
+use std::collections::{BTreeMap, BTreeSet};
+use display_as::{with_template, format_as, HTML, UTF8, DisplayAs};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use crate::atomicfile::AtomicFile;
+use crate::data::{absolute_url, AnyID, representation_hue, representation_map_expanding, stable_hash,
+                  Activity, ActivityID, Concept, ConceptID, ConceptMap, ConceptNode,
+                  ConceptRepresentationID, Course, CourseID, Data, Focus, MapOptions,
+                  Representation, RepresentationID};
+use crate::layout::{Layout, Slot};
+
+impl Data {
+    /// The concept map without waiting for a new layout: if the
+    /// concepts have changed since we last laid out the map, we show
+    /// the old layout while laying out the new one in the background.
+    /// Implied prerequisites may be left out, leaving everything else
+    /// where it was.
+    pub fn latest_concept_map(&self, max_width: usize, hide_implied: bool) -> ConceptMap {
+        let mut layout = self.concept_layout(max_width, false);
+        if hide_implied {
+            let implied: Vec<_> = crate::report::implied_edges(&self.prereq_edges(), None).into_iter()
+                .map(|(e, _)| e)
+                .collect();
+            layout.remove_edges(&implied);
+        }
+        let mut map = self.concept_map_from(&layout, max_width);
+        map.options = Some(MapOptions { max_width, hide_implied });
+        map.make_expensive(self)
+    }
+    /// The concept map drawn as SVG, with each concept linking to its
+    /// page.  Like `latest_concept_map`, this doesn't wait for a new
+    /// layout.
+    pub fn concept_map_svg(&self, max_width: usize) -> String {
+        let mut layout = self.concept_layout(max_width, false);
+        // Concepts with no prerequisites or dependents go in rows at
+        // the bottom.
+        layout.add_rows(&self.unconnected(&layout), max_width);
+        crate::svg::render(&layout, |id| crate::svg::Node {
+            label: crate::mathml::typeset_html(&format_as!(HTML, self.get(id).name)),
+            href: format_as!(HTML, absolute_url(id)),
+            fill: None,
+        })
+    }
+    /// The layout of the concept map, from the saved layouts.
+    /// Unless we `wait`, a changed graph gets the last layout (less
+    /// any concepts that are gone), while we lay it out again in the
+    /// background.
+    pub(crate) fn concept_layout(&self, max_width: usize, wait: bool) -> Layout<ConceptID> {
+        // Wider than every concept is no different from that wide, and
+        // this way nobody can fill our disk by asking for more widths.
+        let max_width = max_width.clamp(1, self.concepts.len().max(1));
+        let edges = self.prereq_edges();
+        // The name of how we lay it out goes in the hash too, so that
+        // layouts saved by an older version get redone.
+        let graph = stable_hash(format!("untangled {:?}", edges).as_bytes());
+        let exists: BTreeSet<ConceptID> = self.concepts.iter().map(|c| c.id).collect();
+        let quick = edges.clone();
+        saved_layout(format!("concept-map {}", max_width), graph, wait,
+                     |c| exists.contains(&c),
+                     || crate::layout::unordered(&quick, max_width),
+                     move |previous| crate::layout::untangled(&edges, max_width, previous))
+    }
+    /// The map of the concepts within `depth` prerequisite steps of
+    /// a concept, either before or after it.
+    pub fn neighborhood_map(&self, id: ConceptID, depth: usize, max_width: usize, wait: bool)
+                            -> ConceptMap
+    {
+        let edges = self.prereq_edges();
+        // No path is longer than there are concepts.
+        let depth = depth.min(self.concepts.len());
+        let mut near: BTreeSet<ConceptID> = std::iter::once(id).collect();
+        let mut further = false;
+        // How far we actually had to go, which is less than the depth
+        // if we ran out of concepts first.
+        let mut reached = 0;
+        for (from, to) in [(0, 1), (1, 0)] {
+            let mut edge: BTreeSet<ConceptID> = std::iter::once(id).collect();
+            let mut steps = 0;
+            loop {
+                let next: BTreeSet<ConceptID> = edges.iter()
+                    .map(|e| [e.0, e.1])
+                    .filter(|e| edge.contains(&e[from]))
+                    .map(|e| e[to])
+                    .filter(|c| !near.contains(c))
+                    .collect();
+                if next.is_empty() {
+                    break;
+                }
+                if steps == depth {
+                    further = true;
+                    break;
+                }
+                near.extend(next.iter().cloned());
+                edge = next;
+                steps += 1;
+            }
+            reached = reached.max(steps);
+        }
+        let depth = if further { depth } else { reached.max(1).min(depth) };
+        let edges: Vec<_> = edges.into_iter()
+            .filter(|(a, b)| near.contains(a) && near.contains(b))
+            .collect();
+        let max_width = max_width.clamp(1, near.len());
+        let graph = stable_hash(format!("layered {:?}", edges).as_bytes());
+        let quick = edges.clone();
+        let layout = saved_layout(format!("neighborhood {} {} {}", id.0, depth, max_width), graph, wait,
+                                  |c| near.contains(&c),
+                                  || crate::layout::unordered(&quick, max_width),
+                                  move |previous| crate::layout::layered(&edges, max_width, previous));
+        let mut rows = self.concept_rows(&layout);
+        if layout.layers.is_empty() {
+            rows.push(vec![ConceptNode::CheapConcept { concept: id, children: Vec::new() }]);
+        }
+        ConceptMap {
+            rows,
+            focus: Some(Focus { concept: self.get(id).clone(), depth, further }),
+            options: None,
+        }.make_expensive(self)
+    }
+    /// The concepts to expand in the map of concept-representations,
+    /// from a comma-separated list of references to them.
+    pub fn expansion(&self, references: &str) -> BTreeSet<ConceptID> {
+        let mut expand = BTreeSet::new();
+        for c in references.split(',').filter_map(|c| self.concept_reference(c)) {
+            if expand.len() == MAX_EXPANDED {
+                break;
+            }
+            expand.insert(c);
+        }
+        expand
+    }
+    /// The map of concepts as the activities teach them, with a node
+    /// for each concept-representation an activity needs or
+    /// introduces, and an edge from each that an activity needs to
+    /// each that it introduces.  Given a representation, we show only
+    /// the concepts in that representation.  Otherwise we can show
+    /// only some concepts (those in `expand`) split into their
+    /// concept-representations, and the rest as one node each.  Each
+    /// expansion has a layout of its own, so we expand no more than
+    /// `MAX_EXPANDED` concepts, and only those that exist.
+    pub fn representation_map(&self, only: Option<RepresentationID>,
+                              expand: Option<&BTreeSet<ConceptID>>,
+                              max_width: usize, wait: bool)
+                              -> RepresentationMap
+    {
+        let expand: Option<BTreeSet<ConceptID>> = if only.is_some() { None } else { expand }
+            .map(|e| e.iter().cloned()
+                 .filter(|&c| self.has(AnyID::Concept(c)))
+                 .take(MAX_EXPANDED)
+                 .collect());
+        let expand = expand.as_ref();
+        let keep = |cr: &&ConceptRepresentationID| only.is_none() || cr.representation == only;
+        let node = |&cr: &ConceptRepresentationID| match expand {
+            Some(expand) if !expand.contains(&cr.concept) => ConceptRepresentationID::from(cr.concept),
+            _ => cr,
+        };
+        let mut nodes = BTreeSet::new();
+        let mut edges = Vec::new();
+        // The representations of each concept, so we know which are
+        // worth expanding.
+        let mut representations: BTreeMap<ConceptID, BTreeSet<RepresentationID>> = BTreeMap::new();
+        for a in self.activities.iter() {
+            for cr in a.prereq_concepts.iter().chain(a.new_concepts.iter()).filter(keep) {
+                nodes.insert(node(cr));
+                representations.entry(cr.concept).or_default().extend(cr.representation);
+            }
+            for p in a.prereq_concepts.iter().filter(keep).map(node) {
+                edges.extend(a.new_concepts.iter().filter(keep).map(node)
+                             .filter(|&n| n != p)
+                             .map(|n| (p, n)));
+            }
+        }
+        edges.sort();
+        edges.dedup();
+        let max_width = max_width.clamp(1, nodes.len().max(1));
+        let graph = stable_hash(format!("layered {:?}", edges).as_bytes());
+        let key = format!("representations {:?} {:?} {}", only.map(|r| r.0),
+                          expand.map(|e| e.iter().map(|c| c.0).collect::<Vec<_>>()), max_width);
+        let quick = edges.clone();
+        let mut layout = saved_layout(key, graph, wait,
+                                      |cr| nodes.contains(&cr),
+                                      || crate::layout::unordered(&quick, max_width),
+                                      move |previous| crate::layout::layered(&edges, max_width, previous));
+        let placed: BTreeSet<ConceptRepresentationID> = layout.edges.iter()
+            .flat_map(|&(a, b)| vec![a, b])
+            .collect();
+        let unplaced: Vec<_> = nodes.iter().filter(|n| !placed.contains(n)).cloned().collect();
+        layout.add_rows(&unplaced, max_width);
+        let svg = crate::svg::render(&layout, |cr| {
+            let mut label = String::new();
+            if let Some(rid) = cr.representation {
+                label.push_str(&format_as!(HTML, self.get(rid).icon));
+                label.push(' ');
+            }
+            label.push_str(&format_as!(HTML, self.name_it(cr)));
+            let split = representations.get(&cr.concept).map(|r| r.len()).unwrap_or(0);
+            let href = match expand {
+                // A concept we drew as one node links to the map with
+                // it expanded, if there is anything to expand and we
+                // may expand another.
+                Some(expand) if cr.representation.is_none() && split > 1 && expand.len() < MAX_EXPANDED => {
+                    label.push_str(&format!(" <small>({} representations)</small>", split));
+                    let mut more = expand.clone();
+                    more.insert(cr.concept);
+                    format_as!(HTML, representation_map_expanding(&more))
+                }
+                _ => format_as!(HTML, absolute_url(cr)),
+            };
+            crate::svg::Node {
+                label: crate::mathml::typeset_html(&label),
+                href,
+                fill: cr.representation.map(representation_color),
+            }
+        });
+        let used: BTreeSet<RepresentationID> = self.activities.iter()
+            .flat_map(|a| a.prereq_concepts.iter().chain(a.new_concepts.iter()))
+            .filter_map(|cr| cr.representation)
+            .collect();
+        RepresentationMap {
+            only: only.map(|r| self.get(r).clone()),
+            expanded: expand.map(|e| e.iter().map(|&c| self.get(c).clone()).collect()),
+            representations: self.representations.iter()
+                .filter(|r| used.contains(&r.id))
+                .cloned()
+                .collect(),
+            svg,
+        }
+    }
+    /// The map of the concepts a course teaches, grouped by the
+    /// activity that first introduces each, with no more than
+    /// `max_width` concepts in a row.  Above them are the concepts
+    /// the course needs from the courses before it.
+    pub fn course_map(&self, id: CourseID, max_width: usize, wait: bool) -> CourseMap {
+        let course = self.get(id).clone();
+        let max_width = max_width.max(1);
+        let edges = self.prereq_edges();
+        let mut taught: BTreeMap<ConceptID, ActivityID> = BTreeMap::new();
+        for &a in course.activities.iter() {
+            for c in self.concepts.iter().filter(|c| self.get(a).new_concepts.contains(&c.id.into())) {
+                taught.entry(c.id).or_insert(a);
+            }
+        }
+        let mut needed: BTreeSet<ConceptID> = edges.iter()
+            .filter(|(a, b)| taught.contains_key(b) && !taught.contains_key(a))
+            .map(|&(a, _)| a)
+            .collect();
+        needed.extend(course.activities.iter()
+                      .flat_map(|&a| self.get(a).prereq_concepts.iter().map(|cr| cr.concept))
+                      .filter(|c| !taught.contains_key(c)));
+        // The course can only build on the courses before it, so what
+        // it needs that none of them teach we list as a warning
+        // rather than show as coming from elsewhere.
+        let earlier: Vec<&Course> = self.courses().take_while(|c| c.id != id).collect();
+        let taught_earlier: BTreeSet<ConceptID> = earlier.iter()
+            .flat_map(|c| c.activities.iter())
+            .flat_map(|&a| self.get(a).new_concepts.iter().map(|cr| cr.concept))
+            .collect();
+        let (needed, unmet): (BTreeSet<ConceptID>, BTreeSet<ConceptID>) = needed.into_iter()
+            .partition(|c| taught_earlier.contains(c));
+
+        // Each group gets as many layers as it needs for the edges
+        // within it to go down.
+        let mut groups: Vec<(Option<ActivityID>, Vec<ConceptID>)> =
+            vec![(None, needed.iter().cloned().collect())];
+        for &a in course.activities.iter() {
+            let concepts: Vec<ConceptID> = taught.iter()
+                .filter(|&(_, &by)| by == a)
+                .map(|(&c, _)| c)
+                .collect();
+            if !concepts.is_empty() {
+                groups.push((Some(a), concepts));
+            }
+        }
+        let mut layer: BTreeMap<ConceptID, usize> = BTreeMap::new();
+        let mut layers_of_group = Vec::new();
+        let mut first = 0;
+        for (_, concepts) in groups.iter() {
+            let mut depth: BTreeMap<ConceptID, usize> = concepts.iter().map(|&c| (c, 0)).collect();
+            // A cycle would never settle, so we stop when it must have.
+            for _ in 0..concepts.len() {
+                for &(a, b) in edges.iter() {
+                    if let (Some(&da), Some(&db)) = (depth.get(&a), depth.get(&b)) {
+                        if db <= da {
+                            depth.insert(b, da + 1);
+                        }
+                    }
+                }
+            }
+            let mut count = 0;
+            for d in 0..=depth.values().max().cloned().unwrap_or(0) {
+                let at_depth: Vec<ConceptID> = concepts.iter().cloned().filter(|c| depth[c] == d).collect();
+                for row in at_depth.chunks(max_width) {
+                    layer.extend(row.iter().map(|&c| (c, first + count)));
+                    count += 1;
+                }
+            }
+            layers_of_group.push(first..first + count);
+            first += count;
+        }
+        let edges: Vec<_> = edges.into_iter()
+            .filter(|(a, b)| layer.contains_key(a) && layer.contains_key(b))
+            .collect();
+        let graph = stable_hash(format!("in layers {:?} {:?}", edges, layer).as_bytes());
+        let quick = (edges.clone(), layer.clone());
+        let layout = saved_layout(format!("course {} {}", id.0, max_width), graph, wait,
+                                  |c| quick.1.contains_key(&c),
+                                  || crate::layout::in_layers_unordered(&quick.0, &quick.1),
+                                  move |previous| crate::layout::in_layers(&edges, &layer, previous));
+        let mut rows = self.concept_rows(&layout).into_iter();
+        let groups = groups.into_iter().zip(layers_of_group)
+            .map(|((activity, _), layers)| CourseMapGroup {
+                activity: activity.map(|a| self.get(a).clone()),
+                rows: rows.by_ref().take(layers.len())
+                    .map(|r| r.into_iter().map(|n| n.make_expensive(self)).collect())
+                    .collect(),
+            })
+            .filter(|g| !g.rows.is_empty())
+            .collect();
+        let from_courses = earlier.into_iter()
+            .filter(|c| c.activities.iter()
+                    .any(|&a| self.get(a).new_concepts.iter().any(|cr| needed.contains(&cr.concept))))
+            .cloned()
+            .collect();
+        let unmet = unmet.into_iter().map(|c| self.get(c).clone()).collect();
+        CourseMap { course, from_courses, unmet, groups }
+    }
+}
+
+/// How many concepts we expand at most in the map of
+/// concept-representations.
+pub const MAX_EXPANDED: usize = 8;
+
+/// Where we keep the last layout of each concept map.
+const LAYOUTS: &str = "concept-map-layouts.json";
+
+/// How many layouts we keep.  Beyond this we forget the ones we saved
+/// longest ago, so that asking for many different maps can't fill
+/// our disk.
+const MAX_SAVED: usize = 64;
+
+/// How many layouts we work out in the background at once.
+const MAX_LAYING_OUT: usize = 2;
+
+/// A layout of a map, and a hash of the graph it lays out.  The
+/// layout is kept as json since each kind of map has its own kind of
+/// node.
+#[derive(Serialize, Deserialize)]
+struct CachedLayout {
+    graph: u64,
+    /// When we saved it, in seconds since the epoch.
+    #[serde(default)]
+    saved: u64,
+    layout: serde_json::Value,
+}
+
+type SavedLayouts = std::collections::BTreeMap<String, CachedLayout>;
+
+lazy_static::lazy_static! {
+    /// The maps we are laying out in the background.  We also hold
+    /// this while reading or writing the saved layouts.
+    static ref LAYING_OUT: std::sync::Mutex<std::collections::BTreeSet<String>> =
+        std::sync::Mutex::new(std::collections::BTreeSet::new());
+}
+
+fn read_layouts() -> SavedLayouts {
+    std::fs::read_to_string(LAYOUTS).ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+/// The saved layout of a map, and the hash of its graph.
+fn load_layout<N: DeserializeOwned>(key: &str) -> Option<(u64, Layout<N>)> {
+    let _lock = LAYING_OUT.lock().unwrap();
+    let cached = read_layouts().remove(key)?;
+    Some((cached.graph, serde_json::from_value(cached.layout).ok()?))
+}
+
+fn save_layout<N: Serialize>(key: String, graph: u64, layout: &Layout<N>) {
+    let saved = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let layout = match serde_json::to_value(layout) {
+        Ok(layout) => layout,
+        Err(_) => return,
+    };
+    let _lock = LAYING_OUT.lock().unwrap();
+    let mut layouts = read_layouts();
+    layouts.insert(key, CachedLayout { graph, saved, layout });
+    while layouts.len() > MAX_SAVED {
+        let oldest = layouts.iter().min_by_key(|(_, c)| c.saved).map(|(k, _)| k.clone()).unwrap();
+        layouts.remove(&oldest);
+    }
+    if let Ok(f) = AtomicFile::create(LAYOUTS) {
+        serde_json::to_writer(&f, &layouts).ok();
+    }
+}
+
+/// The layout of the map named `key`, where `graph` is a hash of
+/// everything its layout depends on.  We save each layout, so we only
+/// lay out each graph once, and a new layout starts from the last
+/// one, so a small edit only makes a small change to the map.  Unless
+/// we `wait`, a changed graph gets the last layout (less any nodes we
+/// don't `keep`), or the `quick` one if we have none, while we
+/// `lay_out` the graph in the background.
+fn saved_layout<N, F>(key: String, graph: u64, wait: bool, keep: impl Fn(N) -> bool,
+                      quick: impl FnOnce() -> Layout<N>, lay_out: F) -> Layout<N>
+where N: Copy + Ord + Serialize + DeserializeOwned + Send + 'static,
+      F: FnOnce(&[Vec<Slot<N>>]) -> Layout<N> + Send + 'static,
+{
+    let cached = load_layout::<N>(&key);
+    if let Some((g, layout)) = &cached {
+        if *g == graph {
+            return layout.clone();
+        }
+    }
+    let previous = cached.as_ref().map(|(_, l)| l.slots()).unwrap_or_default();
+    if wait {
+        let layout = lay_out(&previous);
+        save_layout(key, graph, &layout);
+        return layout;
+    }
+    spawn_layout(key, move || (graph, lay_out(&previous)));
+    match cached {
+        Some((_, mut layout)) => {
+            layout.retain(keep);
+            layout
+        }
+        None => quick(),
+    }
+}
+
+/// Lay out a map in the background and save its layout, unless we
+/// already are, or are already busy with as many as we allow.  In
+/// that case a later request will get to it.
+fn spawn_layout<N, F>(key: String, lay_out: F)
+where N: Serialize + 'static,
+      F: FnOnce() -> (u64, Layout<N>) + Send + 'static,
+{
+    {
+        let mut laying_out = LAYING_OUT.lock().unwrap();
+        if laying_out.len() >= MAX_LAYING_OUT || !laying_out.insert(key.clone()) {
+            return;
+        }
+    }
+    std::thread::spawn(move || {
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(lay_out)) {
+            Ok((graph, layout)) => save_layout(key.clone(), graph, &layout),
+            Err(_) => println!("Error laying out {}!", key),
+        }
+        LAYING_OUT.lock().unwrap().remove(&key);
+    });
+}
+
+pub struct RepresentationMap {
+    /// The representation we show, if only one.
+    only: Option<Representation>,
+    /// The representations that any activity uses.
+    representations: Vec<Representation>,
+    /// The concepts we split into their concept-representations, if
+    /// not all of them.
+    expanded: Option<Vec<Concept>>,
+    svg: String,
+}
+#[with_template("[%" "%]" "representation-map.html")]
+impl DisplayAs<HTML> for RepresentationMap {}
+
+/// The color we draw concepts in a representation with.
+fn representation_color(id: RepresentationID) -> String {
+    format!("hsl({}, 70%, 88%)", representation_hue(id))
+}
+
+/// The url of the map of concept-representations, or of those in one
+/// representation.
+pub fn representation_map_url(only: Option<RepresentationID>) -> String {
+    match only {
+        Some(rid) => format!("/concept-map/representations/{}", rid.0),
+        None => "/concept-map/representations/".to_string(),
+    }
+}
+
+/// The concepts introduced by one activity in a course map, or (with
+/// no activity) those the course needs from elsewhere.
+pub struct CourseMapGroup {
+    activity: Option<Activity>,
+    rows: Vec<Vec<ConceptNode>>,
+}
+
+pub struct CourseMap {
+    course: Course,
+    /// The earlier courses that teach concepts this course needs.
+    from_courses: Vec<Course>,
+    /// The concepts this course needs that no earlier course teaches.
+    unmet: Vec<Concept>,
+    groups: Vec<CourseMapGroup>,
+}
+#[with_template("[%" "%]" "course-map.html")]
+impl DisplayAs<HTML> for CourseMap {}
+
+#[test]
+fn neighborhood_map_reaches_depth_steps() {
+    let data = Data::new();
+    let concept = data.concepts.iter()
+        .find(|c| !c.prereq_concepts.is_empty())
+        .unwrap();
+    let mut expected: Vec<ConceptID> = concept.prereq_concepts.clone();
+    expected.extend(data.concepts.iter()
+                    .filter(|c| c.prereq_concepts.contains(&concept.id))
+                    .map(|c| c.id));
+    expected.push(concept.id);
+    expected.sort();
+    expected.dedup();
+    let map = data.neighborhood_map(concept.id, 1, 4, true);
+    let mut shown: Vec<ConceptID> = map.rows.iter().flatten().filter_map(|n| match n {
+        ConceptNode::Concept { concept, .. } => Some(concept.id),
+        _ => None,
+    }).collect();
+    shown.sort();
+    assert_eq!(shown, expected);
+    assert!(data.neighborhood_map(concept.id, 3, 4, true).rows.iter().flatten().count() >= shown.len());
+}
+
+#[test]
+fn neighborhood_map_stops_when_it_runs_out() {
+    let data = Data::new();
+    let map = data.neighborhood_map(ConceptID(0), usize::MAX, 4, true);
+    let focus = map.focus.unwrap();
+    assert!(!focus.further);
+    assert!(focus.depth <= data.concepts.len());
+    // Asking for exactly as far as it goes, or any further, shows the
+    // same map.
+    for depth in focus.depth..focus.depth + 3 {
+        let same = data.neighborhood_map(ConceptID(0), depth, 4, true);
+        assert_eq!(same.focus.unwrap().depth, focus.depth);
+        assert_eq!(same.rows.len(), map.rows.len());
+        assert_eq!(same.rows.iter().flatten().count(), map.rows.iter().flatten().count());
+    }
+}
+
+#[test]
+fn representation_map_filters_to_one_representation() {
+    use std::collections::BTreeSet;
+    let data = Data::new();
+    let used: BTreeSet<ConceptRepresentationID> = data.activities.iter()
+        .flat_map(|a| a.prereq_concepts.iter().chain(a.new_concepts.iter()))
+        .cloned()
+        .collect();
+    let rid = used.iter().find_map(|cr| cr.representation).unwrap();
+    let links = |map: &RepresentationMap| map.svg.matches("<a href=").count();
+    assert_eq!(links(&data.representation_map(None, None, 4, true)), used.len());
+    assert_eq!(links(&data.representation_map(Some(rid), None, 4, true)),
+               used.iter().filter(|cr| cr.representation == Some(rid)).count());
+
+    // Collapsed, there is a node for each concept, until we expand one.
+    let concepts: BTreeSet<ConceptID> = used.iter().map(|cr| cr.concept).collect();
+    let mut expand = BTreeSet::new();
+    let collapsed = data.representation_map(None, Some(&expand), 4, true);
+    assert_eq!(links(&collapsed), concepts.len());
+    let c = *concepts.iter()
+        .find(|&&c| used.iter().filter(|cr| cr.concept == c && cr.representation.is_some()).count() > 1)
+        .unwrap();
+    assert!(collapsed.svg.contains(&format!("expand={}", format_as!(HTML, c))));
+    expand.insert(c);
+    assert_eq!(links(&data.representation_map(None, Some(&expand), 4, true)),
+               concepts.len() - 1 + used.iter().filter(|cr| cr.concept == c).count());
+}
+
+#[test]
+fn expansion_is_of_few_known_concepts() {
+    let data = Data::new();
+    let all: Vec<String> = data.concepts.iter().map(|c| c.id.0.to_string()).collect();
+    assert_eq!(data.expansion(&all.join(",")).len(), MAX_EXPANDED);
+    let first = data.concepts[0].id;
+    let twice = format!("{},{},{}", first.0, first.0, usize::MAX);
+    assert_eq!(data.expansion(&twice).into_iter().collect::<Vec<_>>(), vec![first]);
+}
+
+#[test]
+fn course_map_groups_concepts_by_activity() {
+    let data = Data::new();
+    for course in data.courses.iter() {
+        let map = data.course_map(course.id, 4, true);
+        for group in map.groups.iter() {
+            let concepts = group.rows.iter().flatten().filter_map(|n| match n {
+                ConceptNode::Concept { concept, .. } => Some(concept.id),
+                _ => None,
+            });
+            for c in concepts {
+                let taught = data.course_is_for_concept(c.into(), course.id);
+                match group.activity {
+                    Some(ref a) => assert!(taught && a.new_concepts.contains(&c.into())),
+                    None => {
+                        assert!(!taught);
+                        assert!(map.from_courses.iter().any(|from| from.activities.iter()
+                            .any(|&a| data.get(a).new_concepts.iter().any(|cr| cr.concept == c))));
+                    }
+                }
+            }
+            assert!(group.rows.iter().all(|r| r.iter().filter(|n| !n.is_fake()).count() <= 4));
+        }
+        let earlier: Vec<CourseID> = data.courses().take_while(|c| c.id != course.id).map(|c| c.id).collect();
+        assert!(map.from_courses.iter().all(|c| earlier.contains(&c.id)));
+        assert!(map.unmet.iter().all(|c| !earlier.iter().any(|&e| data.courses_for_concept(c.id).contains(&e))));
+    }
+}
@@ -1,6 +1,7 @@
 use display_as::{HTML, DisplayAs};
 use std::fmt::{Formatter, Error};
 use serde::{Deserialize, Serialize};
+use crate::sanitize::sanitize_html;
 
 #[derive(Debug,Clone,Eq,PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Markdown(String);
@@ -13,17 +14,138 @@ impl Markdown {
         self.0.len()
     }
     pub fn from_html(html: &str) -> Markdown {
-        // println!("\nstarting with:\n*************\n{}\n********\n", html);
-        let x = html2md::parse_html(html);
-        // preserve "\\" in math mode, which disallows escaping a
-        // backslash, but why would we want a backslash?
-        let x = x.replace(r"\\", r"\\\\");
-        // mode disallow escaping square brackets [] since we want
-        // math mode to *not* require extra escapes.
-        let x = x.replace(r"\[", r"\\[");
-        let x = x.replace(r"\]", r"\\]");
-        // println!("\nending with:\n*************\n{}\n********\n", x.trim());
-        Markdown(x.trim().to_string())
+        // Pull the math and references out of the text before
+        // html2md gets to see them, so they never get
+        // markdown-escaped.  The editor hands us math as the raw TeX
+        // (with its delimiters) that MathJax was given, and
+        // references as the elements we rendered them into.
+        let html = sanitize_html(html);
+        let mut verbatim = Vec::new();
+        let mut hidden = String::with_capacity(html.len());
+        let pieces = split_tags(&html);
+        let mut i = 0;
+        while i < pieces.len() {
+            let piece = pieces[i];
+            i += 1;
+            if piece.starts_with('<') {
+                if let Some(r) = attribute(piece, "data-ref") {
+                    hidden.push_str(&placeholder(verbatim.len()));
+                    verbatim.push(format!("[[{}]]", unescape_html(r)));
+                    i = skip_element(&pieces, i, tag_name(piece));
+                } else {
+                    hidden.push_str(piece);
+                }
+                continue;
+            }
+            for r in split_references(piece) {
+                let t = match r {
+                    Piece::Reference(r) => {
+                        hidden.push_str(&placeholder(verbatim.len()));
+                        verbatim.push(format!("[[{}]]", unescape_html(r)));
+                        continue;
+                    }
+                    Piece::Text(t) => t,
+                };
+                for span in split_math(t) {
+                    match span {
+                        Span::Text(t) => hidden.push_str(t),
+                        Span::Math(m) => {
+                            hidden.push_str(&placeholder(verbatim.len()));
+                            verbatim.push(unescape_html(m));
+                        }
+                    }
+                }
+            }
+        }
+        let x = html2md::parse_html(&hidden);
+        Markdown(restore_math(x.trim(), &verbatim))
+    }
+    /// The references to other things (written `[[c2]]` or `[[Some
+    /// concept]]`) in this markdown.
+    pub fn references(&self) -> Vec<&str> {
+        split_references(&self.0).into_iter()
+            .filter_map(|s| if let Piece::Reference(r) = s { Some(r) } else { None })
+            .collect()
+    }
+    /// Replace references with links, given a way to find the url
+    /// (as html) and current name of the thing referred to.
+    pub fn with_references(&self, resolve: impl Fn(&str) -> Option<(String, String)>)
+                           -> Markdown
+    {
+        let mut out = String::with_capacity(self.0.len());
+        for span in split_references(&self.0) {
+            match span {
+                Piece::Text(t) => out.push_str(t),
+                Piece::Reference(r) => {
+                    if let Some((href, name)) = resolve(r) {
+                        out.push_str(&format!(
+                            r#"<a href={} class="xref" data-ref="{}">{}</a>"#,
+                            href, escape_attribute(r), escape_html(&name)));
+                    } else {
+                        out.push_str(&format!(
+                            r#"<span class="xref unresolved" data-ref="{0}">{1}<span class="hint"> (unresolved)</span></span>"#,
+                            escape_attribute(r), escape_html(r)));
+                    }
+                }
+            }
+        }
+        Markdown(out)
+    }
+    /// The math spans (including their delimiters) in this markdown.
+    pub fn math(&self) -> Vec<&str> {
+        split_math(&self.0).into_iter()
+            .filter_map(|s| if let Span::Math(m) = s { Some(m) } else { None })
+            .collect()
+    }
+    /// The first sentence as plain text (apart from any math), for
+    /// summaries such as the description search engines show.
+    pub fn first_sentence(&self) -> String {
+        let html = self.to_html();
+        let text: String = split_tags(&html).into_iter()
+            .filter(|piece| !piece.starts_with('<'))
+            .map(unescape_html)
+            .collect();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut end = 0;
+        for span in split_math(&text) {
+            match span {
+                Span::Math(m) => end += m.len(),
+                Span::Text(t) => {
+                    let mut chars = t.char_indices().peekable();
+                    while let Some((i, c)) = chars.next() {
+                        let at_end = chars.peek().map(|&(_, next)| next == ' ').unwrap_or(true);
+                        if ['.', '?', '!'].contains(&c) && at_end {
+                            return text[..end + i + 1].to_string();
+                        }
+                    }
+                    end += t.len();
+                }
+            }
+        }
+        text
+    }
+    pub fn to_html(&self) -> String {
+        // Hide the math from pulldown-cmark, so that underscores and
+        // asterisks in TeX are not turned into emphasis.  MathJax is
+        // then handed exactly the TeX that was written.
+        let mut math = Vec::new();
+        let mut hidden = String::with_capacity(self.0.len());
+        for span in split_math(&self.0) {
+            match span {
+                Span::Text(t) => hidden.push_str(t),
+                Span::Math(m) => {
+                    hidden.push_str(&placeholder(math.len()));
+                    math.push(escape_html(m));
+                }
+            }
+        }
+        let parser = pulldown_cmark::Parser::new(&hidden);
+
+        let mut html_buf = String::new();
+        pulldown_cmark::html::push_html(&mut html_buf, parser);
+        // Raw html in the markdown goes straight through
+        // pulldown-cmark, so this is where we make it safe.
+        sanitize_html(&restore_math(&html_buf, &math))
     }
 }
 
@@ -41,10 +163,300 @@ impl Default for Markdown {
 
 impl DisplayAs<HTML> for Markdown {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        let parser = pulldown_cmark::Parser::new(&self.0);
+        f.write_str(&self.to_html())
+    }
+}
 
-        let mut html_buf = String::new();
-        pulldown_cmark::html::push_html(&mut html_buf, parser);
-        f.write_str(&html_buf)
+/// A piece of markdown (or of the text in html), which is either
+/// math to be handed to MathJax untouched, or anything else.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Span<'a> {
+    Text(&'a str),
+    Math(&'a str),
+}
+
+const MATH_DELIMITERS: &[(&str, &str)] = &[
+    ("$$", "$$"),
+    ("$", "$"),
+    (r"\[", r"\]"),
+    (r"\(", r"\)"),
+];
+
+/// Split text into math and non-math, recognizing the same
+/// delimiters that MathJax does.  Backslash escapes are skipped over
+/// as a unit, so `\$` is never a delimiter, and backquoted code is
+/// never math.  An opening delimiter without a close is just text.
+pub(crate) fn split_math(s: &str) -> Vec<Span<'_>> {
+    let mut out = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+    let bytes = s.as_bytes();
+    'outer: while i < bytes.len() {
+        if bytes[i] == b'`' {
+            let ticks = bytes[i..].iter().take_while(|&&b| b == b'`').count();
+            let fence = &s[i..i+ticks];
+            if let Some(end) = s[i+ticks..].find(fence) {
+                i += 2*ticks + end;
+            } else {
+                i += ticks;
+            }
+            continue;
+        }
+        for (open, close) in MATH_DELIMITERS.iter() {
+            if s[i..].starts_with(open) {
+                if let Some(len) = math_length(&s[i+open.len()..], close) {
+                    let end = i + open.len() + len + close.len();
+                    if text_start < i {
+                        out.push(Span::Text(&s[text_start..i]));
+                    }
+                    out.push(Span::Math(&s[i..end]));
+                    i = end;
+                    text_start = end;
+                    continue 'outer;
+                }
+            }
+        }
+        if bytes[i] == b'\\' {
+            i += 1;
+        }
+        i += s[i..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+    }
+    if text_start < s.len() {
+        out.push(Span::Text(&s[text_start..]));
+    }
+    out
+}
+
+/// The length of the TeX preceding `close`, if it is closed.
+fn math_length(s: &str, close: &str) -> Option<usize> {
+    let mut i = 0;
+    while i < s.len() {
+        if s[i..].starts_with(close) {
+            if close == "$" && i == 0 {
+                // "$$" is not an empty inline equation.
+                return None;
+            }
+            return Some(i);
+        }
+        if s[i..].starts_with('\\') {
+            i += 1;
+        }
+        i += s[i..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+    }
+    None
+}
+
+/// A piece of markdown that is either a reference to something else
+/// or anything else, math included.
+#[derive(Debug, PartialEq, Eq)]
+enum Piece<'a> {
+    Text(&'a str),
+    /// What is between the brackets of `[[...]]`.
+    Reference(&'a str),
+}
+
+/// Split text into references and everything else.
+fn split_references(s: &str) -> Vec<Piece<'_>> {
+    // A reference may contain math (a concept name often does), but
+    // its brackets may not be within math.
+    let mut math = Vec::new();
+    let mut at = 0;
+    for span in split_math(s) {
+        match span {
+            Span::Math(m) => {
+                math.push(at..at+m.len());
+                at += m.len();
+            }
+            Span::Text(t) => at += t.len(),
+        }
+    }
+    let in_math = |i: usize| math.iter().any(|r| r.contains(&i));
+    let mut out = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+    while let Some(start) = s[i..].find("[[").map(|x| x + i) {
+        i = start + 2;
+        if in_math(start) {
+            continue;
+        }
+        let end = s[i..].match_indices("]]").map(|(x, _)| x + i).find(|&e| !in_math(e));
+        if let Some(end) = end {
+            let inner = &s[i..end];
+            if !inner.trim().is_empty() && !inner.contains('\n') && !inner.contains("[[") {
+                if start > text_start {
+                    out.push(Piece::Text(&s[text_start..start]));
+                }
+                out.push(Piece::Reference(inner));
+                text_start = end + 2;
+                i = end + 2;
+            }
+        }
+    }
+    if text_start < s.len() {
+        out.push(Piece::Text(&s[text_start..]));
+    }
+    out
+}
+
+/// The name of an html tag, e.g. "a" for `<a href="...">`.
+fn tag_name(tag: &str) -> &str {
+    let tag = tag.trim_start_matches('<').trim_start_matches('/');
+    let end = tag.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(tag.len());
+    &tag[..end]
+}
+
+/// The value of an attribute in an html tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start+len])
+}
+
+/// The index of the piece after the close of the element whose
+/// opening tag precedes `i`.
+fn skip_element(pieces: &[&str], mut i: usize, name: &str) -> usize {
+    let mut depth = 1;
+    while i < pieces.len() && depth > 0 {
+        let p = pieces[i];
+        if p.starts_with("</") && tag_name(p) == name {
+            depth -= 1;
+        } else if p.starts_with('<') && !p.ends_with("/>") && tag_name(p) == name {
+            depth += 1;
+        }
+        i += 1;
+    }
+    i
+}
+
+/// Split html into tags and the text between them.
+pub(crate) fn split_tags(html: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        if start > 0 {
+            out.push(&rest[..start]);
+        }
+        let end = rest[start..].find('>').map(|e| start + e + 1).unwrap_or(rest.len());
+        out.push(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        out.push(rest);
+    }
+    out
+}
+
+fn placeholder(i: usize) -> String {
+    format!("\u{E000}{}\u{E001}", i)
+}
+
+fn restore_math(s: &str, math: &[String]) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('\u{E000}') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + '\u{E000}'.len_utf8()..];
+        let end = after.find('\u{E001}').unwrap_or(after.len());
+        match after[..end].parse::<usize>().ok().and_then(|i| math.get(i)) {
+            Some(m) => out.push_str(m),
+            None => out.push_str(&rest[start..start + '\u{E000}'.len_utf8() + end]),
+        }
+        rest = &after[(end + '\u{E001}'.len_utf8()).min(after.len())..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn escape_attribute(s: &str) -> String {
+    escape_html(s).replace('"', "&quot;")
+}
+
+pub(crate) fn unescape_html(s: &str) -> String {
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"")
+        .replace("&#39;", "'").replace("&nbsp;", "\u{a0}").replace("&amp;", "&")
+}
+
+#[test]
+fn math_is_not_emphasized() {
+    let md = Markdown::new(r"If $v_x$ and $v_y*2$ then \[a_b * c_d\] and $$x_1$$.");
+    let html = md.to_html();
+    assert!(html.contains(r"$v_x$"));
+    assert!(html.contains(r"$v_y*2$"));
+    assert!(html.contains(r"\[a_b * c_d\]"));
+    assert!(html.contains(r"$$x_1$$"));
+    assert!(!html.contains("<em>"));
+    assert_eq!(md.math(), vec![r"$v_x$", r"$v_y*2$", r"\[a_b * c_d\]", r"$$x_1$$"]);
+}
+
+#[test]
+fn math_delimiters_need_closing() {
+    assert_eq!(split_math(r"costs \$5 or $6"), vec![Span::Text(r"costs \$5 or $6")]);
+    assert_eq!(split_math(r"`$x$` is code"), vec![Span::Text(r"`$x$` is code")]);
+    assert_eq!(split_math(r"$a\$b$"), vec![Span::Math(r"$a\$b$")]);
+}
+
+#[test]
+fn math_round_trips_through_html() {
+    let md = Markdown::new(r"The *curl* is $\vec\nabla\times\vec v$ where $v_x < v_y$: \[\frac{\partial v_z}{\partial y}\]");
+    let back = Markdown::from_html(&md.to_html());
+    assert_eq!(back.math(), md.math());
+    assert_eq!(back, md);
+}
+
+#[test]
+fn references_round_trip_through_html() {
+    let md = Markdown::new(r"As in [[c2]] and [[Some *concept*]], but not [[nothing]] or [x] or $[[x]]$.");
+    assert_eq!(md.references(), vec!["c2", "Some *concept*", "nothing"]);
+    assert_eq!(split_references("[[$x$]] and $[[y]]$"),
+               vec![Piece::Reference("$x$"), Piece::Text(" and $[[y]]$")]);
+    assert_eq!(Markdown::new("[[How $f_x$ changes]]").references(), vec!["How $f_x$ changes"]);
+    let html = md.with_references(|r| match r {
+        "c2" => Some((r#""/concept/2""#.to_string(), "Ratio of $d_x$".to_string())),
+        "Some *concept*" => Some((r#""/concept/7""#.to_string(), "Seven".to_string())),
+        _ => None,
+    }).to_html();
+    assert!(html.contains(r#"<a href="/concept/2" class="xref" data-ref="c2">Ratio of $d_x$</a>"#));
+    assert!(html.contains(r#"class="xref unresolved" data-ref="nothing""#));
+    assert_eq!(Markdown::from_html(&html), md);
+}
+
+#[test]
+fn raw_html_is_sanitized() {
+    let md = Markdown::new("Hello <script>alert(1)</script><img src=x onerror=alert(1)> $a<b$");
+    let html = md.to_html();
+    assert!(!html.contains("script") && !html.contains("onerror"));
+    assert!(html.contains("$a&lt;b$"));
+    let md = Markdown::from_html(r#"<p onclick="alert(1)">Hi<script>alert(1)</script> $x_1$</p>"#);
+    assert_eq!(md, Markdown::new("Hi $x_1$"));
+}
+
+#[test]
+fn first_sentence_is_plain_text() {
+    let md = Markdown::new("The *gradient* $\\nabla f$ points\nuphill. It is a vector.");
+    assert_eq!(md.first_sentence(), "The gradient $\\nabla f$ points uphill.");
+    let md = Markdown::new("If $x = 1. y$ holds, see [it](/concept/2)! Then");
+    assert_eq!(md.first_sentence(), "If $x = 1. y$ holds, see it!");
+    assert_eq!(Markdown::new("No end").first_sentence(), "No end");
+}
+
+#[test]
+fn descriptions_in_progression_yaml() {
+    // The real corpus: every description we have must hand its math
+    // to MathJax unchanged, and must survive a trip through the
+    // editor unchanged.
+    let data = crate::data::Data::new();
+    let mut num_math = 0;
+    for (_, md) in data.descriptions() {
+        let html = md.to_html();
+        for m in md.math() {
+            assert!(html.contains(&escape_html(m)), "lost math {:?} in {}", m, html);
+            num_math += 1;
+        }
+        assert_eq!(Markdown::from_html(&html).math(), md.math());
     }
+    assert!(num_math > 50);
 }
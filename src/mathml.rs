@@ -0,0 +1,596 @@
+//! Convert the TeX in our pages to MathML, so that the mirror can be
+//! read without running MathJax.  We only understand the subset of
+//! TeX that our descriptions actually use; anything else is left
+//! alone for MathJax to typeset in the browser.
+
+use display_as::{format_as, HTML, DisplayAs};
+use std::cell::Cell;
+use std::fmt::{Formatter, Error};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::markdown::{split_math, split_tags, unescape_html, Span};
+
+static MIRROR_MATHML: AtomicBool = AtomicBool::new(false);
+thread_local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    static FELL_BACK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Decide whether the mirror should be typeset on the server.
+pub fn set_mirror_mathml(on: bool) {
+    MIRROR_MATHML.store(on, Ordering::Relaxed);
+}
+pub fn mirror_mathml() -> bool {
+    MIRROR_MATHML.load(Ordering::Relaxed)
+}
+
+/// Typeset math in pages rendered on this thread.
+pub fn set_active(on: bool) {
+    ACTIVE.with(|a| a.set(on));
+}
+fn is_active() -> bool {
+    ACTIVE.with(|a| a.get())
+}
+
+/// Forget any math that the last page fell back to MathJax for, so
+/// it can't leak into the next page.  This should be called before
+/// rendering each page.
+pub fn start_page() {
+    FELL_BACK.with(|f| f.set(false));
+}
+
+/// Does the page we just rendered still need MathJax?  This resets
+/// the check, so it should be asked once at the end of each page.
+pub fn needs_mathjax() -> bool {
+    let fell_back = FELL_BACK.with(|f| f.replace(false));
+    !is_active() || fell_back
+}
+
+/// Display some html with its math converted to MathML, when we are
+/// typesetting on the server.
+pub struct Typeset<'a, T>(&'a T);
+pub fn typeset<T: DisplayAs<HTML>>(x: &T) -> Typeset<'_, T> {
+    Typeset(x)
+}
+impl<T: DisplayAs<HTML>> DisplayAs<HTML> for Typeset<'_, T> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        if is_active() {
+            f.write_str(&typeset_html(&format_as!(HTML, self.0)))
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
+/// Convert the math in the text of some html.
+pub fn typeset_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut raw_until: Option<String> = None;
+    for piece in split_tags(html) {
+        if let Some(tag) = piece.strip_prefix('<') {
+            let name: String = tag.chars()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '/')
+                .collect::<String>().to_ascii_lowercase();
+            if let Some(ref closing) = raw_until {
+                if &name == closing {
+                    raw_until = None;
+                }
+            } else if ["script", "style", "title", "code", "pre", "textarea"].contains(&&name[..]) {
+                raw_until = Some(format!("/{}", name));
+            }
+            out.push_str(piece);
+        } else if raw_until.is_some() {
+            out.push_str(piece);
+        } else {
+            for span in split_math(piece) {
+                match span {
+                    Span::Text(t) => out.push_str(t),
+                    Span::Math(m) => {
+                        if let Some(mathml) = math_to_mathml(&unescape_html(m)) {
+                            out.push_str(&mathml);
+                        } else {
+                            FELL_BACK.with(|f| f.set(true));
+                            out.push_str(m);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Convert a math span (including its delimiters) to MathML.
+pub fn math_to_mathml(math: &str) -> Option<String> {
+    let (tex, display) = if math.starts_with("$$") {
+        (&math[2..math.len()-2], true)
+    } else if math.starts_with('$') {
+        (&math[1..math.len()-1], false)
+    } else {
+        (&math[2..math.len()-2], math.starts_with(r"\["))
+    };
+    tex_to_mathml(tex, display)
+}
+
+/// Convert TeX to MathML, or `None` if it uses TeX we don't know.
+pub fn tex_to_mathml(tex: &str, display: bool) -> Option<String> {
+    let mut p = Parser { toks: tokenize(tex)?, i: 0 };
+    let body = p.row()?;
+    if p.peek().is_some() {
+        return None; // an unmatched "}" or "\right"
+    }
+    Some(format!(
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="{}"><semantics>{}<annotation encoding="application/x-tex">{}</annotation></semantics></math>"#,
+        if display { "block" } else { "inline" }, body, escape(tex)))
+}
+
+/// A math span (including its delimiters) as plain Unicode text, for
+/// places such as Graphviz labels that can show neither MathML nor
+/// MathJax.  TeX we don't know is left as its command names.
+pub fn math_to_text(math: &str) -> String {
+    let tex = if math.starts_with("$$") || !math.starts_with('$') {
+        &math[2..math.len()-2]
+    } else {
+        &math[1..math.len()-1]
+    };
+    match tokenize(tex) {
+        Some(toks) => {
+            let mut i = 0;
+            let mut out = String::new();
+            while i < toks.len() {
+                out.push_str(&text_item(&toks, &mut i));
+            }
+            out.split_whitespace().collect::<Vec<_>>().join(" ")
+        }
+        None => tex.to_string(),
+    }
+}
+
+/// The text of the argument of a command or script.
+fn text_argument(toks: &[Tok], i: &mut usize) -> String {
+    while toks.get(*i) == Some(&Tok::Space) {
+        *i += 1;
+    }
+    text_item(toks, i)
+}
+
+/// The text of one token, or of a whole group.
+fn text_item(toks: &[Tok], i: &mut usize) -> String {
+    let t = match toks.get(*i) {
+        Some(t) => t.clone(),
+        None => return String::new(),
+    };
+    *i += 1;
+    // Anything longer than a symbol needs parentheses to be a
+    // numerator or such.
+    let wrapped = |x: String| if x.chars().count() > 1 { format!("({})", x) } else { x };
+    match t {
+        Tok::Open => {
+            let mut out = String::new();
+            while *i < toks.len() && toks[*i] != Tok::Close {
+                out.push_str(&text_item(toks, i));
+            }
+            *i += 1;
+            out
+        }
+        Tok::Close => String::new(),
+        Tok::Char(c) => c.to_string(),
+        // Spaces in math don't show, except in text.
+        Tok::Space => String::new(),
+        Tok::Sub => format!("_{}", text_argument(toks, i)),
+        Tok::Sup => format!("^{}", text_argument(toks, i)),
+        Tok::Command(c) => {
+            if let Some(letter) = greek(&c) {
+                return letter.to_string();
+            }
+            if let Some(op) = operator(&c) {
+                let code = u32::from_str_radix(&op[3..op.len()-1], 16).ok();
+                return format!(" {} ", code.and_then(std::char::from_u32).unwrap_or('?'));
+            }
+            match &c as &str {
+                "partial" => "∂".to_string(),
+                "nabla" => "∇".to_string(),
+                "infty" => "∞".to_string(),
+                "int" => "∫".to_string(),
+                "oint" => "∮".to_string(),
+                "sum" => "∑".to_string(),
+                "prod" => "∏".to_string(),
+                "frac" => {
+                    let num = wrapped(text_argument(toks, i));
+                    format!("{}/{}", num, wrapped(text_argument(toks, i)))
+                }
+                "sqrt" => format!("√{}", wrapped(text_argument(toks, i))),
+                "vec" => format!("{}\u{20D7}", text_argument(toks, i)),
+                "hat" => format!("{}\u{0302}", text_argument(toks, i)),
+                "bar" => format!("{}\u{0304}", text_argument(toks, i)),
+                "left" | "right" => match text_argument(toks, i).as_str() {
+                    "." => String::new(),
+                    d => d.to_string(),
+                },
+                "text" | "textrm" | "mathrm" | "textbf" | "mathbf" => {
+                    while toks.get(*i) == Some(&Tok::Space) {
+                        *i += 1;
+                    }
+                    let mut out = String::new();
+                    if toks.get(*i) == Some(&Tok::Open) {
+                        *i += 1;
+                        while let Some(t) = toks.get(*i) {
+                            *i += 1;
+                            match t {
+                                Tok::Close => break,
+                                Tok::Char(c) => out.push(*c),
+                                _ => out.push(' '),
+                            }
+                        }
+                    }
+                    format!(" {} ", out)
+                }
+                " " | "," | ";" | ":" | "quad" => " ".to_string(),
+                "!" => String::new(),
+                // Functions are followed by a space, as they would be
+                // typeset.
+                c if c.chars().all(|x| x.is_ascii_alphabetic()) => format!("{} ", c),
+                // Braces and such.
+                c => c.to_string(),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Tok {
+    Command(String),
+    Char(char),
+    Open,
+    Close,
+    Sub,
+    Sup,
+    /// Any run of whitespace, which matters only in text.
+    Space,
+}
+
+fn tokenize(tex: &str) -> Option<Vec<Tok>> {
+    let mut out = Vec::new();
+    let mut cs = tex.chars().peekable();
+    while let Some(c) = cs.next() {
+        match c {
+            '\\' => {
+                let first = cs.next()?;
+                let mut name = first.to_string();
+                if first.is_ascii_alphabetic() {
+                    while let Some(&c) = cs.peek() {
+                        if !c.is_ascii_alphabetic() {
+                            break;
+                        }
+                        name.push(c);
+                        cs.next();
+                    }
+                }
+                out.push(Tok::Command(name));
+            }
+            '{' => out.push(Tok::Open),
+            '}' => out.push(Tok::Close),
+            '_' => out.push(Tok::Sub),
+            '^' => out.push(Tok::Sup),
+            '&' | '#' | '%' | '~' => return None,
+            c if c.is_whitespace() => {
+                if out.last() != Some(&Tok::Space) {
+                    out.push(Tok::Space);
+                }
+            }
+            c => out.push(Tok::Char(c)),
+        }
+    }
+    Some(out)
+}
+
+struct Parser {
+    toks: Vec<Tok>,
+    i: usize,
+}
+
+impl Parser {
+    /// The next token that isn't whitespace.
+    fn peek(&self) -> Option<&Tok> {
+        self.toks[self.i.min(self.toks.len())..].iter().find(|&t| *t != Tok::Space)
+    }
+    fn next(&mut self) -> Option<Tok> {
+        while self.toks.get(self.i) == Some(&Tok::Space) {
+            self.i += 1;
+        }
+        let t = self.toks.get(self.i).cloned();
+        self.i += 1;
+        t
+    }
+    /// Parse until the end of the input or of the current group,
+    /// leaving the "}" or "\right" to be consumed by the caller.
+    fn row(&mut self) -> Option<String> {
+        let mut out = String::new();
+        while let Some(t) = self.peek() {
+            if *t == Tok::Close || matches!(t, Tok::Command(c) if c == "right") {
+                break;
+            }
+            out.push_str(&self.scripted()?);
+        }
+        Some(format!("<mrow>{}</mrow>", out))
+    }
+    /// An atom along with any subscript and superscript.
+    fn scripted(&mut self) -> Option<String> {
+        let (base, under) = self.atom()?;
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek() {
+                Some(Tok::Sub) if sub.is_none() => {
+                    self.next();
+                    sub = Some(self.argument()?);
+                }
+                Some(Tok::Sup) if sup.is_none() => {
+                    self.next();
+                    sup = Some(self.argument()?);
+                }
+                Some(Tok::Char('\'')) if sup.is_none() => {
+                    self.next();
+                    sup = Some("<mo>&#x2032;</mo>".to_string());
+                }
+                _ => break,
+            }
+        }
+        let (sub_tag, sup_tag, both_tag) = if under {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        Some(match (sub, sup) {
+            (None, None) => base,
+            (Some(b), None) => format!("<{0}>{1}{2}</{0}>", sub_tag, base, b),
+            (None, Some(p)) => format!("<{0}>{1}{2}</{0}>", sup_tag, base, p),
+            (Some(b), Some(p)) => format!("<{0}>{1}{2}{3}</{0}>", both_tag, base, b, p),
+        })
+    }
+    /// The argument of a command or script, which is a single token
+    /// or a braced group.
+    fn argument(&mut self) -> Option<String> {
+        match self.peek()? {
+            Tok::Open => {
+                self.next();
+                let r = self.row()?;
+                if self.next()? != Tok::Close {
+                    return None;
+                }
+                Some(r)
+            }
+            Tok::Char(c) if c.is_ascii_digit() => {
+                let c = *c;
+                self.next();
+                Some(format!("<mn>{}</mn>", c))
+            }
+            _ => Some(self.atom()?.0),
+        }
+    }
+    /// The raw text of a braced group, for \text and friends.
+    fn text_argument(&mut self) -> Option<String> {
+        if self.next()? != Tok::Open {
+            return None;
+        }
+        let mut text = String::new();
+        loop {
+            // Read the tokens as they are, since spaces count here.
+            let t = self.toks.get(self.i).cloned();
+            self.i += 1;
+            match t? {
+                Tok::Close => return Some(text),
+                Tok::Char(c) => text.push(c),
+                Tok::Space => text.push(' '),
+                Tok::Command(ref c) if c == " " => text.push(' '),
+                _ => return None,
+            }
+        }
+    }
+    /// A single thing, and whether its scripts go under and over it.
+    fn atom(&mut self) -> Option<(String, bool)> {
+        Some(match self.next()? {
+            Tok::Open => {
+                let r = self.row()?;
+                if self.next()? != Tok::Close {
+                    return None;
+                }
+                (r, false)
+            }
+            Tok::Char(c) if c.is_ascii_digit() || c == '.' => {
+                let mut n = c.to_string();
+                while let Some(Tok::Char(c)) = self.peek() {
+                    if !c.is_ascii_digit() && *c != '.' {
+                        break;
+                    }
+                    n.push(*c);
+                    self.next();
+                }
+                (format!("<mn>{}</mn>", n), false)
+            }
+            Tok::Char('-') => ("<mo>&#x2212;</mo>".to_string(), false),
+            Tok::Char(c) if "+=<>()[],/|!:;*?".contains(c) => {
+                (format!("<mo>{}</mo>", escape(&c.to_string())), false)
+            }
+            Tok::Char(c) if c.is_alphabetic() => (format!("<mi>{}</mi>", c), false),
+            Tok::Char(c) if !c.is_ascii() => (format!("<mo>{}</mo>", c), false),
+            Tok::Command(c) => self.command(&c)?,
+            _ => return None,
+        })
+    }
+    fn command(&mut self, c: &str) -> Option<(String, bool)> {
+        if let Some(letter) = greek(c) {
+            let variant = if c.chars().next()?.is_uppercase() {
+                r#" mathvariant="normal""#
+            } else {
+                ""
+            };
+            return Some((format!("<mi{}>{}</mi>", variant, letter), false));
+        }
+        if let Some(op) = operator(c) {
+            return Some((format!("<mo>{}</mo>", op), false));
+        }
+        Some(match c {
+            "partial" => ("<mi mathvariant=\"normal\">&#x2202;</mi>".to_string(), false),
+            "nabla" => ("<mi mathvariant=\"normal\">&#x2207;</mi>".to_string(), false),
+            "infty" => ("<mi mathvariant=\"normal\">&#x221E;</mi>".to_string(), false),
+            "int" => ("<mo largeop=\"true\">&#x222B;</mo>".to_string(), false),
+            "oint" => ("<mo largeop=\"true\">&#x222E;</mo>".to_string(), false),
+            "sum" => ("<mo largeop=\"true\">&#x2211;</mo>".to_string(), true),
+            "prod" => ("<mo largeop=\"true\">&#x220F;</mo>".to_string(), true),
+            "lim" => ("<mo>lim</mo>".to_string(), true),
+            "sin" | "cos" | "tan" | "ln" | "log" | "exp" => {
+                (format!("<mi>{}</mi><mo>&#x2061;</mo>", c), false)
+            }
+            "frac" => {
+                let num = self.argument()?;
+                let den = self.argument()?;
+                (format!("<mfrac>{}{}</mfrac>", num, den), false)
+            }
+            "sqrt" => (format!("<msqrt>{}</msqrt>", self.argument()?), false),
+            "vec" => (format!("<mover accent=\"true\">{}<mo>&#x2192;</mo></mover>",
+                              self.argument()?), false),
+            "hat" => (format!("<mover accent=\"true\">{}<mo>^</mo></mover>",
+                              self.argument()?), false),
+            "bar" => (format!("<mover accent=\"true\">{}<mo>&#x00AF;</mo></mover>",
+                              self.argument()?), false),
+            "text" | "textrm" | "mathrm" => {
+                (format!("<mtext>{}</mtext>", escape(&self.text_argument()?)), false)
+            }
+            "textbf" | "mathbf" => {
+                (format!("<mtext mathvariant=\"bold\">{}</mtext>",
+                         escape(&self.text_argument()?)), false)
+            }
+            "left" => {
+                let open = self.delimiter()?;
+                let inner = self.row()?;
+                if self.next()? != Tok::Command("right".to_string()) {
+                    return None;
+                }
+                let close = self.delimiter()?;
+                (format!("<mrow>{}{}{}</mrow>", open, inner, close), false)
+            }
+            " " | "," | ";" | ":" => ("<mspace width=\"0.25em\"/>".to_string(), false),
+            "quad" => ("<mspace width=\"1em\"/>".to_string(), false),
+            "!" => ("<mspace width=\"-0.17em\"/>".to_string(), false),
+            "{" | "}" => (format!("<mo>{}</mo>", c), false),
+            _ => return None,
+        })
+    }
+    /// The delimiter following \left or \right.
+    fn delimiter(&mut self) -> Option<String> {
+        let d = match self.next()? {
+            Tok::Char('.') => return Some(String::new()),
+            Tok::Char(c) if "()[]|/".contains(c) => c.to_string(),
+            Tok::Command(ref c) if c == "{" || c == "}" => c.to_string(),
+            _ => return None,
+        };
+        Some(format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", d))
+    }
+}
+
+fn greek(name: &str) -> Option<char> {
+    Some(match name {
+        "alpha" => 'α', "beta" => 'β', "gamma" => 'γ', "delta" => 'δ',
+        "epsilon" => 'ϵ', "varepsilon" => 'ε', "zeta" => 'ζ', "eta" => 'η',
+        "theta" => 'θ', "iota" => 'ι', "kappa" => 'κ', "lambda" => 'λ',
+        "mu" => 'μ', "nu" => 'ν', "xi" => 'ξ', "pi" => 'π', "rho" => 'ρ',
+        "sigma" => 'σ', "tau" => 'τ', "upsilon" => 'υ', "phi" => 'ϕ',
+        "varphi" => 'φ', "chi" => 'χ', "psi" => 'ψ', "omega" => 'ω',
+        "Gamma" => 'Γ', "Delta" => 'Δ', "Theta" => 'Θ', "Lambda" => 'Λ',
+        "Xi" => 'Ξ', "Pi" => 'Π', "Sigma" => 'Σ', "Phi" => 'Φ', "Psi" => 'Ψ',
+        "Omega" => 'Ω',
+        _ => return None,
+    })
+}
+
+fn operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "cdot" => "&#x22C5;",
+        "times" => "&#x00D7;",
+        "pm" => "&#x00B1;",
+        "rightarrow" | "to" => "&#x2192;",
+        "leftarrow" => "&#x2190;",
+        "Rightarrow" => "&#x21D2;",
+        "neq" | "ne" => "&#x2260;",
+        "leq" | "le" => "&#x2264;",
+        "geq" | "ge" => "&#x2265;",
+        "approx" => "&#x2248;",
+        "equiv" => "&#x2261;",
+        "propto" => "&#x221D;",
+        _ => return None,
+    })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[test]
+fn converts_what_we_use() {
+    let m = tex_to_mathml(r"\left(\frac{\partial U}{\partial S}\right)_V", true).unwrap();
+    assert!(m.contains(r#"display="block""#));
+    assert!(m.contains("<msub><mrow><mo fence=\"true\" stretchy=\"true\">(</mo>"));
+    assert!(m.contains("<mfrac><mrow><mi mathvariant=\"normal\">&#x2202;</mi><mi>U</mi></mrow>"));
+    assert!(tex_to_mathml(r"\frac1{x^{A-1}}", false).unwrap()
+            .contains("<mfrac><mn>1</mn><mrow><msup><mi>x</mi><mrow><mi>A</mi><mo>&#x2212;</mo><mn>1</mn></mrow></msup></mrow></mfrac>"));
+    assert!(tex_to_mathml(r"\lim_{h \rightarrow 0} f", false).unwrap()
+            .contains("<munder><mo>lim</mo>"));
+}
+
+#[test]
+fn text_keeps_its_spaces() {
+    // From the descriptions of the curl and of partial derivatives.
+    assert!(tex_to_mathml(r"\hat z \text{ in rectangular coodinates}", true).unwrap()
+            .contains("<mtext> in rectangular coodinates</mtext>"));
+    assert!(tex_to_mathml(r"\textrm{Leibniz Notation}\ \frac{\partial f}{\partial x}", false).unwrap()
+            .contains("<mtext>Leibniz Notation</mtext>"));
+    assert!(tex_to_mathml(r"\mathbf{ F }", false).unwrap()
+            .contains("<mtext mathvariant=\"bold\"> F </mtext>"));
+}
+
+#[test]
+fn math_as_text() {
+    assert_eq!(math_to_text(r"$d\vec r$"), "dr\u{20D7}");
+    assert_eq!(math_to_text(r"$\left(\frac{\partial f}{\partial x}\right)_y$"), "((∂f)/(∂x))_y");
+    assert_eq!(math_to_text(r"$\rho / \epsilon_0$"), "ρ/ϵ_0");
+    assert_eq!(math_to_text(r"\(\vec F\cdot d\vec A\)"), "F\u{20D7} ⋅ dA\u{20D7}");
+    assert_eq!(math_to_text(r"$\hat z \text{ in rectangular coodinates}$"),
+               "z\u{0302} in rectangular coodinates");
+}
+
+#[test]
+fn math_as_text_handles_delimiters_and_scripts() {
+    assert_eq!(math_to_text(r"$$x^{2}$$"), "x^2");
+    assert_eq!(math_to_text(r"\[v_{x} + \sqrt{2}\]"), "v_x+√2");
+    assert_eq!(math_to_text(r"$\frac12$"), "1/2");
+    assert_eq!(math_to_text(r"$\frac{a+b}{c}$"), "(a+b)/c");
+}
+
+#[test]
+fn math_as_text_handles_symbols_and_spacing() {
+    assert_eq!(math_to_text(r"$\alpha\Omega$"), "αΩ");
+    assert_eq!(math_to_text(r"$\bar x \le \infty$"), "x\u{0304} ≤ ∞");
+    assert_eq!(math_to_text(r"$a\,b\!c$"), "a bc");
+    assert_eq!(math_to_text(r"$\left. x \right|$"), "x|");
+    // A function name is kept apart from its argument.
+    assert_eq!(math_to_text(r"$\sin\theta$"), "sin θ");
+}
+
+#[test]
+fn unknown_tex_falls_back() {
+    assert_eq!(tex_to_mathml(r"\begin{align} a &= b \end{align}", true), None);
+    assert_eq!(tex_to_mathml(r"\frac{a}{b", false), None);
+    assert_eq!(tex_to_mathml(r"a}", false), None);
+    set_active(true);
+    let html = typeset_html(r"<p>We know $x^2$ but not $\mathcal{L}$.</p>");
+    assert!(html.contains("<msup><mi>x</mi><mn>2</mn></msup>"));
+    assert!(html.contains(r"$\mathcal{L}$"));
+    assert!(needs_mathjax());
+    assert!(!needs_mathjax());
+    // A page that never asks still doesn't leave its fallback to the
+    // next one.
+    typeset_html(r"$\mathcal{L}$");
+    start_page();
+    assert!(!needs_mathjax());
+    set_active(false);
+}
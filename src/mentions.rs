@@ -0,0 +1,85 @@
+use display_as::{with_template, HTML, DisplayAs};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use crate::data::{absolute_url, AnyID, ConceptRepresentationID, Data};
+
+impl Data {
+    /// The index of what mentions each thing, which we build the
+    /// first time we need it.
+    pub(crate) fn mentions(&self) -> &BTreeMap<AnyID, Vec<AnyID>> {
+        self.mentions.get_or_init(|| {
+            let mut mentions: BTreeMap<AnyID, Vec<AnyID>> = BTreeMap::new();
+            for (from, md) in self.descriptions() {
+                for r in md.references() {
+                    let to = match self.resolve_reference(r) {
+                        Some(AnyID::ConceptRepresentation(ConceptRepresentationID {
+                            concept, representation: None,
+                        })) => AnyID::Concept(concept),
+                        Some(id) => id,
+                        None => continue,
+                    };
+                    if to != from {
+                        let v = mentions.entry(to).or_default();
+                        if !v.contains(&from) {
+                            v.push(from);
+                        }
+                    }
+                }
+            }
+            for v in mentions.values_mut() {
+                v.sort();
+            }
+            mentions
+        })
+    }
+    /// Everything whose description mentions this (or, for a
+    /// concept, any of its representations).
+    pub fn mentions_of(&self, id: AnyID) -> MentionedIn {
+        let mut ids: Vec<AnyID> = self.mentions().iter()
+            .filter(|&(&to, _)| to == id || match (to, id) {
+                (AnyID::ConceptRepresentation(cr), AnyID::Concept(c)) => cr.concept == c,
+                _ => false,
+            })
+            .flat_map(|(_, v)| v.iter().cloned())
+            .filter(|&from| from != id)
+            .collect();
+        ids.sort();
+        ids.dedup();
+        MentionedIn(ids.into_iter().map(|id| Mention { id, name: self.name_of(id) }).collect())
+    }
+}
+
+/// Something whose description mentions whatever we are viewing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Mention {
+    pub id: AnyID,
+    pub name: String,
+}
+#[with_template(r#"<a href="# absolute_url(self.id) r#" class="mention">"# self.name r#"</a>"#)]
+impl DisplayAs<HTML> for Mention {}
+
+/// Everything that mentions whatever we are viewing.
+#[derive(Debug, Clone)]
+pub struct MentionedIn(pub Vec<Mention>);
+#[with_template("[%" "%]" "mentioned-in.html")]
+impl DisplayAs<HTML> for MentionedIn {}
+
+#[test]
+fn mentions_follow_descriptions() {
+    use crate::data::{ActivityID, ConceptID};
+    use crate::markdown::Markdown;
+    let mut data = Data::new();
+    let name = data.concepts[1].name.clone();
+    let name = name.replace('\u{a0}', " ");
+    data.activities[0].long_description = Markdown::new(&format!("See [[c0]] and [[{}]].", name));
+    assert!(data.mentions_of(AnyID::Concept(ConceptID(0))).0.iter()
+            .any(|m| m.id == AnyID::Activity(ActivityID(0))));
+    assert!(data.mentions_of(AnyID::Concept(ConceptID(1))).0.iter()
+            .any(|m| m.id == AnyID::Activity(ActivityID(0))));
+
+    // The index is built once, so an edit needs a fresh one.
+    data.activities[0].long_description = Markdown::new("Nothing to see.");
+    data.mentions.take();
+    assert!(data.mentions_of(AnyID::Concept(ConceptID(0))).0.iter()
+            .all(|m| m.id != AnyID::Activity(ActivityID(0))));
+}
@@ -0,0 +1,375 @@
+//! The static mirror of the site, which we rebuild a page at a time
+//! as the pages' inputs change.
+
+use display_as::{format_as, HTML, URL};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use crate::data::{absolute_url, page_file, published_url, relative_mirror,
+                  representation_map_url, set_canonical_page, set_current_page,
+                  stable_hash, AnyID, CourseID, Data, RepresentationID};
+
+const STATE: &str = "mirror/.build-state";
+
+/// A single file in the mirror.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Page {
+    /// The page for a concept, activity, course or representation.
+    Entity(AnyID),
+    /// The json for a concept, activity or representation.
+    Json(AnyID),
+    Index,
+    Courses,
+    Representations,
+    /// A concept map of a given width, or the default one.
+    ConceptMap(Option<usize>),
+    /// The concept map of a course.
+    CourseMap(CourseID),
+    /// The map of concept-representations, or of one representation.
+    RepresentationMap(Option<RepresentationID>),
+    /// The list of pages for search engines.
+    Sitemap,
+}
+
+impl Page {
+    fn url(&self) -> String {
+        match *self {
+            Page::Entity(id) => format_as!(URL, id),
+            Page::Json(id) => format_as!(URL, "/api" id),
+            Page::Index => "/".to_string(),
+            Page::Courses => "/courses".to_string(),
+            Page::Representations => "/representations".to_string(),
+            Page::ConceptMap(Some(width)) => format!("/concept-map/{}", width),
+            Page::ConceptMap(None) => "/concept-map/".to_string(),
+            Page::CourseMap(id) => format_as!(URL, "/concept-map" id),
+            Page::RepresentationMap(only) => representation_map_url(only),
+            Page::Sitemap => "/sitemap.xml".to_string(),
+        }
+    }
+    fn path(&self) -> String {
+        let url = self.url();
+        match *self {
+            Page::Json(_) => format!("mirror{}", url),
+            _ if relative_mirror() => format!("mirror/{}", page_file(&url)),
+            _ if url.ends_with('/') => format!("mirror{}index.html", url),
+            _ => format!("mirror{}", url),
+        }
+    }
+    fn render(&self, data: &Data) -> String {
+        match *self {
+            Page::Entity(AnyID::Concept(id)) => format_as!(HTML, data.concept_view(id)),
+            Page::Entity(AnyID::Activity(id)) => format_as!(HTML, data.activity_view(id)),
+            Page::Entity(AnyID::Course(id)) => format_as!(HTML, data.course_view(id)),
+            Page::Entity(AnyID::Representation(id)) => {
+                format_as!(HTML, data.representation_view(id))
+            }
+            Page::Entity(AnyID::ConceptRepresentation(_)) => {
+                panic!("concept representations do not have pages")
+            }
+            Page::Json(id) => data.json(id).expect("json for something without json"),
+            Page::Index => format_as!(HTML, data.progression_view()),
+            Page::Courses => format_as!(HTML, data.course_list()),
+            Page::Representations => format_as!(HTML, data.all_representations()),
+            Page::ConceptMap(width) => {
+                format_as!(HTML, data.concept_map(width.unwrap_or(4)))
+            }
+            Page::CourseMap(id) => format_as!(HTML, data.course_map(id, 4, true)),
+            Page::RepresentationMap(only) => format_as!(HTML, data.representation_map(only, None, 4, true)),
+            Page::Sitemap => sitemap(data),
+        }
+    }
+    /// How many links away something can be and still affect this
+    /// page, or `None` if everything can.
+    fn reach(&self) -> Option<usize> {
+        match *self {
+            // A course shows the pages of its activities, more or less.
+            Page::Entity(AnyID::Course(_)) => Some(4),
+            Page::Entity(_) => Some(3),
+            Page::Json(_) => Some(1),
+            Page::Index | Page::Courses | Page::Representations | Page::ConceptMap(_) | Page::CourseMap(_)
+                | Page::RepresentationMap(_) | Page::Sitemap => None,
+        }
+    }
+}
+
+/// Every page in the mirror, slowest last.
+fn pages(data: &Data) -> Vec<Page> {
+    let mut pages = Vec::new();
+    for (id, _) in data.entity_json() {
+        pages.push(Page::Entity(id));
+        match id {
+            AnyID::Course(id) => pages.push(Page::CourseMap(id)),
+            AnyID::Representation(id) => pages.push(Page::RepresentationMap(Some(id))),
+            _ => (),
+        }
+        if data.json(id).is_some() {
+            pages.push(Page::Json(id));
+        }
+    }
+    pages.push(Page::Index);
+    pages.push(Page::Courses);
+    pages.push(Page::Representations);
+    pages.push(Page::Sitemap);
+    pages.push(Page::RepresentationMap(None));
+    pages.extend((1..18).map(|width| Page::ConceptMap(Some(width))));
+    pages.push(Page::ConceptMap(None));
+    pages
+}
+
+/// A hash that stays the same from one build of the program to the
+/// next, since we save it with the mirror.
+fn hash(x: impl std::fmt::Debug) -> u64 {
+    stable_hash(format!("{:?}", x).as_bytes())
+}
+
+/// A hash of everything each page depends on, so a page needs
+/// rebuilding exactly when its fingerprint changes.
+fn fingerprints(data: &Data) -> BTreeMap<String, u64> {
+    let entities: BTreeMap<AnyID, u64> = data.entity_json().into_iter()
+        .map(|(id, json)| (id, stable_hash(json.as_bytes())))
+        .collect();
+    // Every page lists the names of everything when editing.
+    let names = hash(entities.keys().map(|&id| data.name_of(id)).collect::<Vec<_>>());
+    // Pages list courses in the order they are taught, which the
+    // entities come in but the hashes above forget.
+    let course_order = hash(data.entity_json().into_iter()
+                            .filter_map(|(id, _)| match id {
+                                AnyID::Course(id) => Some(id),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>());
+    let settings = hash((env!("CARGO_PKG_VERSION"),
+                         format_as!(HTML, absolute_url("")),
+                         relative_mirror(),
+                         crate::mathml::mirror_mathml()));
+    // A concept map depends on the graph it lays out, and on what its
+    // nodes show of each concept.
+    let concept_map = hash((data.concepts.iter()
+                            .map(|c| (c.id, &c.name, &c.prereq_concepts, &c.long_description,
+                                      c.representations.keys().collect::<Vec<_>>()))
+                            .collect::<Vec<_>>(),
+                            data.representations.iter()
+                            .map(|r| (r.id, &r.name, format_as!(HTML, r.icon)))
+                            .collect::<Vec<_>>()));
+    let mut neighbors: BTreeMap<AnyID, BTreeSet<AnyID>> = BTreeMap::new();
+    for (a, b) in data.links() {
+        neighbors.entry(a).or_default().insert(b);
+        neighbors.entry(b).or_default().insert(a);
+    }
+    let mut out = BTreeMap::new();
+    for page in pages(data) {
+        let deps: BTreeSet<AnyID> = match (page, page.reach()) {
+            (Page::Entity(id), Some(reach)) | (Page::Json(id), Some(reach)) => {
+                let mut deps: BTreeSet<AnyID> = std::iter::once(id).collect();
+                let mut edge = deps.clone();
+                for _ in 0..reach {
+                    edge = edge.iter()
+                        .flat_map(|x| neighbors.get(x).into_iter().flatten())
+                        .filter(|x| !deps.contains(x))
+                        .cloned()
+                        .collect();
+                    deps.extend(edge.iter().cloned());
+                }
+                deps
+            }
+            // The sitemap needs only the names, which we always hash.
+            (Page::Sitemap, _) => BTreeSet::new(),
+            (Page::ConceptMap(_), _) => {
+                out.insert(page.path(), hash((settings, names, course_order, concept_map)));
+                continue;
+            }
+            _ => entities.keys().cloned().collect(),
+        };
+        let deps: Vec<(String, u64)> = deps.into_iter()
+            .map(|id| (format_as!(HTML, id), entities[&id]))
+            .collect();
+        out.insert(page.path(), hash((settings, names, course_order, deps)));
+    }
+    out
+}
+
+/// Bring the mirror up to date, rewriting only pages whose inputs
+/// have changed since the last build (or every page, if asked),
+/// copying in the files those pages use, and removing anything left
+/// over from earlier builds.
+pub fn build(data: &Data, everything: bool) {
+    crate::mathml::set_active(crate::mathml::mirror_mathml());
+    let old: BTreeMap<String, u64> = if everything {
+        BTreeMap::new()
+    } else {
+        std::fs::read_to_string(STATE).ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    };
+    let new = fingerprints(data);
+    let mut keep: BTreeSet<PathBuf> = std::iter::once(PathBuf::from(STATE)).collect();
+    let mut rebuilt = 0;
+    for page in pages(data) {
+        let path = page.path();
+        keep.insert(PathBuf::from(&path));
+        if old.get(&path) == new.get(&path) && Path::new(&path).exists() {
+            continue;
+        }
+        if let Page::ConceptMap(_) = page {
+            println!("creating {}...", path);
+        }
+        if let Some(dir) = Path::new(&path).parent() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        if relative_mirror() {
+            set_current_page(Some(page.url()));
+        }
+        set_canonical_page(Some(page.url()));
+        crate::mathml::start_page();
+        std::fs::write(&path, page.render(data)).ok();
+        set_current_page(None);
+        set_canonical_page(None);
+        rebuilt += 1;
+    }
+    std::fs::write(STATE, serde_json::to_string_pretty(&new).unwrap()).ok();
+    crate::mathml::set_active(false);
+
+    let mut copied = 0;
+    for asset in assets(data) {
+        let to = Path::new("mirror").join(&asset);
+        if copy_if_changed(&asset, &to) {
+            copied += 1;
+        }
+        keep.insert(to);
+    }
+    let mut removed = 0;
+    for f in files_in("mirror") {
+        if !keep.contains(&f) && std::fs::remove_file(&f).is_ok() {
+            removed += 1;
+            // This only succeeds once the directory is empty.
+            f.parent().map(std::fs::remove_dir);
+        }
+    }
+    println!("Rebuilt {} of {} pages in the mirror, copied {} files and removed {}.",
+             rebuilt, new.len(), copied, removed);
+}
+
+/// A sitemap listing the home page and the page of every concept,
+/// activity, course and representation.
+fn sitemap(data: &Data) -> String {
+    let mut out = String::from(concat!(r#"<?xml version="1.0" encoding="UTF-8"?>"#, "\n",
+                                       r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#,
+                                       "\n"));
+    for page in pages(data) {
+        if let Page::Index | Page::Entity(_) = page {
+            let url = published_url(&page.url()).replace('&', "&amp;").replace('<', "&lt;");
+            out.push_str(&format!("  <url><loc>{}</loc></url>\n", url));
+        }
+    }
+    out.push_str("</urlset>\n");
+    out
+}
+
+/// The files besides the pages themselves that the mirror needs.
+fn assets(data: &Data) -> Vec<PathBuf> {
+    let mut out = vec![PathBuf::from("style.css"), PathBuf::from("libraries/jquery-3.3.1.js")];
+    // MathJax loads much of itself as it goes, so we copy all of it.
+    out.extend(files_in("libraries/MathJax-2.7.5"));
+    out.extend(data.figures().into_iter().map(PathBuf::from));
+    out.into_iter().filter(|f| f.is_file()).collect()
+}
+
+/// Every file within a directory.
+pub(crate) fn files_in(dir: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut out = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for e in entries.flatten() {
+            let path = e.path();
+            if path.is_dir() {
+                out.extend(files_in(&path));
+            } else {
+                out.push(path);
+            }
+        }
+    }
+    out
+}
+
+/// Copy a file unless the copy looks up to date, returning whether
+/// we copied it.
+fn copy_if_changed(from: &Path, to: &Path) -> bool {
+    if let (Ok(f), Ok(t)) = (from.metadata(), to.metadata()) {
+        let newer = match (f.modified(), t.modified()) {
+            (Ok(f), Ok(t)) => f > t,
+            _ => true,
+        };
+        if f.len() == t.len() && !newer {
+            return false;
+        }
+    }
+    if let Some(dir) = to.parent() {
+        std::fs::create_dir_all(dir).unwrap();
+    }
+    std::fs::copy(from, to).is_ok()
+}
+
+struct Job {
+    running: bool,
+    pending: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref JOB: std::sync::Mutex<Job> = std::sync::Mutex::new(Job {
+        running: false,
+        pending: false,
+    });
+}
+
+/// How long we wait after an edit before building the mirror, so
+/// that a burst of edits leads to a single build.
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Bring the mirror up to date in the background.  If a build is
+/// already waiting or running, one more follows it, so the mirror
+/// always ends up reflecting the latest edit.
+pub fn spawn_build() {
+    {
+        let mut job = JOB.lock().unwrap();
+        if job.running {
+            job.pending = true;
+            return;
+        }
+        job.running = true;
+    }
+    std::thread::spawn(|| loop {
+        std::thread::sleep(DEBOUNCE);
+        // The build we are about to start includes every edit so far.
+        JOB.lock().unwrap().pending = false;
+        let result = std::panic::catch_unwind(|| build(&Data::new(), false));
+        if result.is_err() {
+            println!("Error building the mirror!");
+        }
+        let mut job = JOB.lock().unwrap();
+        if job.pending {
+            job.pending = false;
+        } else {
+            job.running = false;
+            return;
+        }
+    });
+}
+
+#[test]
+fn edits_only_touch_nearby_pages() {
+    let mut data = Data::new();
+    let before = fingerprints(&data);
+    let id = data.entity_json().into_iter()
+        .filter_map(|(id, _)| if let AnyID::Activity(id) = id { Some(id) } else { None })
+        .next().unwrap();
+    data.get_activity(id).external_url = Some("changed".to_string());
+    let after = fingerprints(&data);
+    let changed: Vec<_> = before.keys().filter(|p| before[*p] != after[*p]).collect();
+    assert!(changed.contains(&&format_as!(URL, "mirror" id)));
+    assert!(changed.contains(&&"mirror/index.html".to_string()));
+    assert!(!changed.contains(&&"mirror/concept-map/index.html".to_string()));
+    assert!(changed.len() < before.len());
+
+    // Renaming a concept changes the concept maps.
+    data.concepts[0].name.push_str(" renamed");
+    let renamed = fingerprints(&data);
+    assert_ne!(renamed["mirror/concept-map/index.html"], after["mirror/concept-map/index.html"]);
+}
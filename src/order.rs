@@ -0,0 +1,70 @@
+use display_as::{with_template, HTML, DisplayAs};
+use crate::data::{absolute_url, Activity, ActivityID, Course, CourseID, Data};
+
+/// A course's activities, and the order we suggest for them.
+pub struct CourseOrder {
+    course: Course,
+    current: Vec<Activity>,
+    suggested: Vec<Activity>,
+}
+#[with_template("[%" "%]" "course-order.html")]
+impl DisplayAs<HTML> for CourseOrder {}
+
+impl Data {
+    /// An order of a course's activities in which each comes after
+    /// the activities that introduce what it needs, changing the
+    /// current order as little as we can.  What an earlier course
+    /// introduces can come anywhere.
+    pub fn suggested_order(&self, id: CourseID) -> Vec<ActivityID> {
+        let earlier: Vec<ActivityID> = self.courses().take_while(|c| c.id != id)
+            .flat_map(|c| c.activities.iter().cloned())
+            .collect();
+        let mut left = self.get(id).activities.clone();
+        let mut order: Vec<ActivityID> = Vec::new();
+        let ready = |a: ActivityID, left: &[ActivityID], order: &[ActivityID]| {
+            self.get(a).prereq_concepts.iter().all(|&cr| {
+                earlier.iter().chain(order.iter()).any(|&x| self.introduces(x, cr))
+                    || !left.iter().any(|&x| x != a && self.introduces(x, cr))
+            })
+        };
+        while !left.is_empty() {
+            // Take the first activity that is ready, or the first of
+            // all if there is a cycle.
+            let next = left.iter().position(|&a| ready(a, &left, &order)).unwrap_or(0);
+            order.push(left.remove(next));
+        }
+        order
+    }
+    /// The page comparing a course's order of activities with the
+    /// order we suggest.
+    pub fn course_order(&self, id: CourseID) -> CourseOrder {
+        let course = self.get(id).clone();
+        CourseOrder {
+            current: course.activities.iter().map(|&a| self.get(a).clone()).collect(),
+            suggested: self.suggested_order(id).into_iter().map(|a| self.get(a).clone()).collect(),
+            course,
+        }
+    }
+}
+
+#[test]
+fn suggested_order_introduces_prereqs_first() {
+    let mut data = Data::new();
+    let ids: Vec<CourseID> = data.courses.iter().map(|c| c.id).collect();
+    for id in ids {
+        let late = |data: &Data| data.prereq_warnings(id).into_iter()
+            .filter(|w| w.introduced_later.as_ref().map(|(c, _)| c.id) == Some(id))
+            .count();
+        let before = late(&data);
+        let order = data.suggested_order(id);
+        let mut sorted = order.clone();
+        sorted.sort();
+        let mut current = data.get(id).activities.clone();
+        current.sort();
+        assert_eq!(sorted, current);
+        data.get_mut(id).activities = order.clone();
+        assert!(late(&data) <= before);
+        // A good order is left alone.
+        assert_eq!(data.suggested_order(id), order);
+    }
+}
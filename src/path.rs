@@ -0,0 +1,144 @@
+use display_as::{with_template, HTML, DisplayAs};
+use serde::Serialize;
+use crate::data::{absolute_url, Activity, Concept, ConceptID, Course, Data};
+
+/// A concept on the way to another, with what teaches it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PathStep {
+    pub concept: Concept,
+    /// The activities that have it among their new concepts.
+    pub activities: Vec<Activity>,
+    /// The courses those activities are in.
+    pub courses: Vec<Course>,
+}
+
+/// The concepts someone still needs in order to learn a concept,
+/// each after its own prerequisites.
+#[derive(Debug, Clone, Serialize)]
+pub struct LearningPath {
+    pub from: Vec<Concept>,
+    pub to: Concept,
+    /// The concepts needed, ending with `to` itself, unless it is
+    /// already known.
+    pub steps: Vec<PathStep>,
+}
+
+/// The page that asks for a learning path, and shows it.
+#[derive(Debug, Clone)]
+pub struct PathPage {
+    /// Every concept, to choose from.
+    pub concepts: Vec<Concept>,
+    pub from: Vec<ConceptID>,
+    pub to: Option<ConceptID>,
+    /// The concepts asked for that we couldn't find.
+    pub unknown: Vec<String>,
+    pub path: Option<LearningPath>,
+}
+#[with_template("[%" "%]" "path.html")]
+impl DisplayAs<HTML> for PathPage {}
+
+impl Data {
+    /// The concepts someone who knows the concepts `from` still needs
+    /// in order to learn `to`, in an order in which they could learn
+    /// them.  We assume that anyone who knows a concept knows its
+    /// prerequisites.
+    pub fn learning_path(&self, from: &[ConceptID], to: ConceptID) -> LearningPath {
+        use std::collections::BTreeSet;
+        let ancestors = |start: Vec<ConceptID>, known: &BTreeSet<ConceptID>| {
+            let mut found = BTreeSet::new();
+            let mut stack = start;
+            while let Some(c) = stack.pop() {
+                if !known.contains(&c) && found.insert(c) {
+                    stack.extend(self.get(c).prereq_concepts.iter().cloned());
+                }
+            }
+            found
+        };
+        let known = ancestors(from.to_vec(), &BTreeSet::new());
+        let needed = ancestors(vec![to], &known);
+        // Each concept comes after the needed prerequisites we have
+        // not yet placed, unless there is a cycle.
+        let mut left: Vec<ConceptID> = needed.into_iter().collect();
+        let mut order = Vec::new();
+        while !left.is_empty() {
+            let next = left.iter()
+                .position(|&c| self.get(c).prereq_concepts.iter().all(|&p| p == c || !left.contains(&p)))
+                .unwrap_or(0);
+            order.push(left.remove(next));
+        }
+        let steps = order.into_iter().map(|c| {
+            let activities: Vec<Activity> = self.activities.iter()
+                .filter(|a| a.new_concepts.iter().any(|cr| cr.concept == c))
+                .cloned()
+                .collect();
+            let courses = self.courses()
+                .filter(|course| activities.iter().any(|a| course.activities.contains(&a.id)))
+                .cloned()
+                .collect();
+            PathStep { concept: self.get(c).clone(), activities, courses }
+        }).collect();
+        LearningPath {
+            from: from.iter().map(|&c| self.get(c).clone()).collect(),
+            to: self.get(to).clone(),
+            steps,
+        }
+    }
+    /// The learning path page, for a query that may have a `to`
+    /// concept and any number of `from` concepts, which may also be
+    /// separated by commas.
+    pub fn path_page(&self, query: &[(String, String)]) -> PathPage {
+        let mut unknown = Vec::new();
+        let mut find = |reference: &str| {
+            let found = self.concept_reference(reference);
+            if found.is_none() {
+                unknown.push(reference.trim().to_string());
+            }
+            found
+        };
+        let mut from = Vec::new();
+        let mut to = None;
+        for (key, value) in query.iter() {
+            match key.as_str() {
+                "from" => from.extend(value.split(',').filter(|r| !r.trim().is_empty())
+                                      .filter_map(&mut find)),
+                "to" => to = find(value),
+                _ => (),
+            }
+        }
+        PathPage {
+            concepts: self.concepts.clone(),
+            path: to.map(|to| self.learning_path(&from, to)),
+            from,
+            to,
+            unknown,
+        }
+    }
+}
+
+#[test]
+fn learning_path_puts_prereqs_first() {
+    let data = Data::new();
+    for c in data.concepts.iter() {
+        let path = data.learning_path(&[], c.id);
+        assert_eq!(path.steps.last().map(|s| s.concept.id), Some(c.id));
+        for (i, step) in path.steps.iter().enumerate() {
+            for p in step.concept.prereq_concepts.iter() {
+                assert!(path.steps[..i].iter().any(|s| s.concept.id == *p));
+            }
+            for a in step.activities.iter() {
+                assert!(a.new_concepts.iter().any(|cr| cr.concept == step.concept.id));
+            }
+        }
+        // Knowing a prerequisite means knowing what it needs, too.
+        for &p in c.prereq_concepts.iter() {
+            let before = data.learning_path(&[], p);
+            let rest = data.learning_path(&[p], c.id);
+            assert!(rest.steps.iter().all(|s| before.steps.iter().all(|b| b.concept.id != s.concept.id)));
+        }
+    }
+    let page = data.path_page(&[("to".to_string(), "c1".to_string()),
+                                ("from".to_string(), "0, nonsense".to_string())]);
+    assert_eq!(page.from, vec![ConceptID(0)]);
+    assert_eq!(page.unknown, vec!["nonsense".to_string()]);
+    assert!(page.path.is_some());
+}
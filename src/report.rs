@@ -0,0 +1,205 @@
+use display_as::{with_template, HTML, DisplayAs};
+use crate::data::{absolute_url, AnyID, Activity, ActivityID, Concept, ConceptID,
+                  ConceptRepresentationID, ChangeRelationship, Course, CourseID, Data};
+
+/// A `[[reference]]` in a description that doesn't match anything.
+#[derive(Debug, Clone)]
+pub struct UnresolvedReference {
+    pub from: AnyID,
+    pub from_name: String,
+    pub reference: String,
+}
+
+/// A prerequisite of a concept that is also a prerequisite of one of
+/// its other prerequisites, and so needn't be listed.
+#[derive(Debug, Clone)]
+pub struct ImpliedPrereq {
+    pub concept: Concept,
+    pub prereq: Concept,
+    /// The concepts on a chain of prerequisites from `prereq` to
+    /// `concept`.
+    pub via: Vec<Concept>,
+}
+#[with_template("[%" "%]" "implied-prereq.html")]
+impl DisplayAs<HTML> for ImpliedPrereq {}
+
+/// An activity in a course that needs a concept-representation that
+/// neither that course nor any before it has introduced yet.
+#[derive(Debug, Clone)]
+pub struct PrereqWarning {
+    pub course: Course,
+    pub activity: Activity,
+    /// The name of the concept-representation it needs.
+    pub prereq: String,
+    /// The first activity after this one that introduces it, and the
+    /// course it is in, if any does.
+    pub introduced_later: Option<(Course, Activity)>,
+}
+#[with_template("[%" "%]" "prereq-warning.html")]
+impl DisplayAs<HTML> for PrereqWarning {}
+
+/// Problems with the data that someone ought to fix.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub unresolved: Vec<UnresolvedReference>,
+    pub implied: Vec<ImpliedPrereq>,
+    pub prereq_warnings: Vec<PrereqWarning>,
+}
+#[with_template("[%" "%]" "report.html")]
+impl DisplayAs<HTML> for Report {}
+
+impl Data {
+    /// Things in the data that need fixing.
+    pub fn report(&self) -> Report {
+        let mut unresolved = Vec::new();
+        for (id, md) in self.descriptions() {
+            for r in md.references() {
+                if self.resolve_reference(r).is_none() {
+                    unresolved.push(UnresolvedReference {
+                        from: id,
+                        from_name: self.name_of(id),
+                        reference: r.to_string(),
+                    });
+                }
+            }
+        }
+        Report {
+            unresolved,
+            implied: self.implied_prereqs(),
+            prereq_warnings: self.courses().flat_map(|c| self.prereq_warnings(c.id)).collect(),
+        }
+    }
+    /// The activities in a course that need a concept-representation
+    /// before it has been introduced, either earlier in the course or
+    /// in a course before it.  A prerequisite with no representation
+    /// is introduced by any representation of its concept.
+    pub fn prereq_warnings(&self, id: CourseID) -> Vec<PrereqWarning> {
+        let introduces = |a, cr| self.introduces(a, cr);
+        // Every activity, in the order the courses teach them.
+        let sequence: Vec<(CourseID, ActivityID)> = self.courses()
+            .flat_map(|c| c.activities.iter().map(move |&a| (c.id, a)))
+            .collect();
+        let mut warnings = Vec::new();
+        for (i, &(course, a)) in sequence.iter().enumerate().filter(|(_, &(c, _))| c == id) {
+            let prereqs = &self.get(a).prereq_concepts;
+            for (j, &cr) in prereqs.iter().enumerate() {
+                if prereqs[..j].contains(&cr)
+                    || sequence[..i].iter().any(|&(_, earlier)| introduces(earlier, cr))
+                {
+                    continue;
+                }
+                warnings.push(PrereqWarning {
+                    course: self.get(course).clone(),
+                    activity: self.get(a).clone(),
+                    prereq: self.name_it(cr),
+                    introduced_later: sequence[i + 1..].iter()
+                        .find(|&&(_, later)| introduces(later, cr))
+                        .map(|&(c, later)| (self.get(c).clone(), self.get(later).clone())),
+                });
+            }
+        }
+        warnings
+    }
+    /// Whether an activity introduces a concept-representation, which
+    /// any representation of the concept does if it has none.
+    pub(crate) fn introduces(&self, a: ActivityID, cr: ConceptRepresentationID) -> bool {
+        self.get(a).new_concepts.iter()
+            .any(|&n| n == cr || (cr.representation.is_none() && n.concept == cr.concept))
+    }
+    /// The prerequisites that follow from other prerequisites, which
+    /// the transitive reduction of the prerequisite graph leaves out.
+    pub fn implied_prereqs(&self) -> Vec<ImpliedPrereq> {
+        self.implied_prereqs_into(None)
+    }
+    /// The implied prerequisites, or just those of one concept,
+    /// which saves searching the whole graph for a concept page.
+    pub(crate) fn implied_prereqs_into(&self, concept: Option<ConceptID>) -> Vec<ImpliedPrereq> {
+        implied_edges(&self.prereq_edges(), concept).into_iter()
+            .map(|((prereq, concept), via)| ImpliedPrereq {
+                concept: self.get(concept).clone(),
+                prereq: self.get(prereq).clone(),
+                via: via.into_iter().map(|c| self.get(c).clone()).collect(),
+            })
+            .collect()
+    }
+}
+
+/// The edges of a graph that are implied by a longer path, each
+/// with the nodes in between on such a path.  Leaving these out
+/// gives the transitive reduction of a graph with no cycles.  Given
+/// a node `into`, only the edges into that node are checked.
+pub(crate) fn implied_edges<N: Copy + Ord>(edges: &[(N, N)], into: Option<N>) -> Vec<((N, N), Vec<N>)> {
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+    let edges: BTreeSet<(N, N)> = edges.iter().cloned().filter(|(a, b)| a != b).collect();
+    let mut children: BTreeMap<N, Vec<N>> = BTreeMap::new();
+    for &(a, b) in edges.iter() {
+        children.entry(a).or_default().push(b);
+    }
+    let mut out = Vec::new();
+    for &(from, to) in edges.iter().filter(|&&(_, to)| into.is_none() || into == Some(to)) {
+        // Search for another way from `from` to `to`, remembering
+        // how we reached each node.
+        let mut reached_from: BTreeMap<N, N> = BTreeMap::new();
+        let mut queue: VecDeque<N> = children[&from].iter().cloned().filter(|&c| c != to).collect();
+        for &c in queue.iter() {
+            reached_from.insert(c, from);
+        }
+        while let Some(n) = queue.pop_front() {
+            if n == to {
+                break;
+            }
+            for &c in children.get(&n).into_iter().flatten() {
+                if c != from && !reached_from.contains_key(&c) {
+                    reached_from.insert(c, n);
+                    queue.push_back(c);
+                }
+            }
+        }
+        if reached_from.contains_key(&to) {
+            let mut via = Vec::new();
+            let mut n = reached_from[&to];
+            while n != from {
+                via.push(n);
+                n = reached_from[&n];
+            }
+            via.reverse();
+            out.push(((from, to), via));
+        }
+    }
+    out
+}
+
+#[test]
+fn implied_edges_have_longer_paths() {
+    let edges = [(1, 2), (2, 3), (1, 3), (3, 4), (1, 4), (5, 6)];
+    assert_eq!(implied_edges(&edges, None), vec![((1, 3), vec![2]), ((1, 4), vec![3])]);
+    assert_eq!(implied_edges(&edges, Some(4)), vec![((1, 4), vec![3])]);
+    assert_eq!(implied_edges(&edges, Some(2)), vec![]);
+    // In a cycle every edge has another way around, but not one that
+    // leaves and comes back to the start.
+    assert_eq!(implied_edges(&[(1, 2), (2, 1)], None), vec![]);
+}
+
+#[test]
+fn prereq_warnings_point_later() {
+    let data = Data::new();
+    for course in data.courses.iter() {
+        for w in data.prereq_warnings(course.id) {
+            assert_eq!(w.course.id, course.id);
+            let position = |c: CourseID, a: ActivityID| {
+                (c, data.get(c).activities.iter().position(|&x| x == a).unwrap())
+            };
+            if let Some((ref later_course, ref later)) = w.introduced_later {
+                assert!(position(later_course.id, later.id) > position(course.id, w.activity.id));
+            }
+        }
+    }
+    // Nothing is taught before the first activity of all.
+    let first = data.courses.iter().find(|c| !c.activities.is_empty()).unwrap();
+    let a = data.get(first.activities[0]);
+    let mut needs: Vec<_> = a.prereq_concepts.clone();
+    needs.sort();
+    needs.dedup();
+    assert_eq!(data.prereq_warnings(first.id).iter().filter(|w| w.activity.id == a.id).count(),
+               needs.len());
+}
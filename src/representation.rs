@@ -1,6 +1,7 @@
 use display_as::{with_template, HTML, URL, UTF8, DisplayAs};
 use serde::{Serialize, Deserialize};
-use crate::data::{absolute_url, RepresentationID, ActivityGroup, Child, ConceptRepresentationView};
+use crate::data::{absolute_url, RepresentationID, ActivityGroup, Child, ConceptRepresentationView,
+                  MentionedIn};
 use crate::markdown::Markdown;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -27,6 +28,7 @@ pub struct RepresentationView {
     pub id: RepresentationID,
     pub name: String,
     pub description: Markdown,
+    pub mentioned_in: MentionedIn,
     pub icon: Icon,
     pub groups: Vec<ActivityGroup>,
     pub other_concepts: Vec<Child<ConceptRepresentationView>>,
@@ -0,0 +1,249 @@
+//! Trimming user-written html down to what our descriptions need.
+
+/// Elements we keep (with only their allowed attributes).
+const ALLOWED: &[&str] = &[
+    "a", "abbr", "b", "blockquote", "br", "caption", "cite", "code", "col", "colgroup",
+    "dd", "del", "div", "dl", "dt", "em", "figcaption", "figure",
+    "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img", "ins", "kbd", "li", "ol",
+    "p", "pre", "q", "s", "small", "span", "strong", "sub", "sup",
+    "table", "tbody", "td", "tfoot", "th", "thead", "tr", "u", "ul",
+];
+
+/// Elements we drop along with everything inside them.  Any other
+/// element we drop, but keep its contents.
+const DROPPED: &[&str] = &[
+    "applet", "embed", "frame", "frameset", "head", "iframe", "math", "noembed",
+    "noscript", "object", "plaintext", "script", "select", "style", "svg",
+    "template", "textarea", "title", "xmp",
+];
+
+fn attribute_allowed(element: &str, attribute: &str) -> bool {
+    matches!((element, attribute),
+             (_, "class") | (_, "title") | (_, "data-ref")
+             | ("a", "href")
+             | ("img", "src") | ("img", "alt") | ("img", "width") | ("img", "height")
+             | ("td", "colspan") | ("td", "rowspan") | ("td", "align")
+             | ("th", "colspan") | ("th", "rowspan") | ("th", "align") | ("th", "scope")
+             | ("ol", "start"))
+}
+
+/// Whether a url is one we are willing to link to, which means it
+/// is relative or uses a boring scheme.
+fn url_allowed(url: &str) -> bool {
+    // Browsers ignore whitespace and control characters in a scheme.
+    let url: String = url.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>().to_lowercase();
+    match url.find([':', '/', '?', '#']) {
+        Some(i) if url[i..].starts_with(':') => {
+            matches!(&url[..i], "http" | "https" | "mailto")
+        }
+        _ => true,
+    }
+}
+
+/// Remove anything from html that could run code or otherwise
+/// misbehave, keeping text, math, links, images and tables.
+pub(crate) fn sanitize_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut i = 0;
+    while let Some(lt) = html[i..].find('<').map(|x| x + i) {
+        out.push_str(&html[i..lt]);
+        let rest = &html[lt..];
+        if rest.starts_with("<!--") {
+            i = rest.find("-->").map(|e| lt + e + 3).unwrap_or(html.len());
+            continue;
+        }
+        let closing = rest[1..].starts_with('/');
+        let name_start = if closing { 2 } else { 1 };
+        let name_len = rest[name_start..].find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len() - name_start);
+        if name_len == 0 && !rest[1..].starts_with('!') && !rest[1..].starts_with('?') {
+            // A bare "<" is just text.
+            out.push_str("&lt;");
+            i = lt + 1;
+            continue;
+        }
+        let name = rest[name_start..name_start+name_len].to_ascii_lowercase();
+        let end = match tag_end(rest) {
+            Some(e) => lt + e,
+            None => {
+                // An unfinished tag could swallow whatever follows
+                // it on the page, so treat it as text.
+                out.push_str("&lt;");
+                i = lt + 1;
+                continue;
+            }
+        };
+        i = end + 1;
+        if closing {
+            if ALLOWED.contains(&name.as_str()) {
+                out.push_str(&format!("</{}>", name));
+            }
+        } else if DROPPED.contains(&name.as_str()) {
+            let close = format!("</{}", name);
+            i = html[i..].to_ascii_lowercase().find(&close)
+                .and_then(|c| html[i+c..].find('>').map(|e| i + c + e + 1))
+                .unwrap_or(html.len());
+        } else if ALLOWED.contains(&name.as_str()) {
+            out.push('<');
+            out.push_str(&name);
+            for (a, v) in attributes(&html[lt+name_start+name_len..end]) {
+                if attribute_allowed(&name, &a)
+                    && ((a != "href" && a != "src") || url_allowed(&v))
+                {
+                    out.push_str(&format!(r#" {}="{}""#, a, escape_attribute(&v)));
+                }
+            }
+            out.push_str(if html[..end].ends_with('/') { " />" } else { ">" });
+        }
+    }
+    if i < html.len() {
+        out.push_str(&html[i..]);
+    }
+    out
+}
+
+/// The index of the ">" that ends the tag at the start of s, taking
+/// quoted attribute values into account.
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(i),
+            (Some(q), c) if q == c => quote = None,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The attributes in the inside of a tag, with their values decoded.
+fn attributes(s: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let len = rest.find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return out;
+        }
+        let name = rest[..len].to_ascii_lowercase();
+        rest = rest[len..].trim_start();
+        let mut value = String::new();
+        if let Some(r) = rest.strip_prefix('=') {
+            let r = r.trim_start();
+            let (v, after) = match r.chars().next() {
+                Some(q) if q == '"' || q == '\'' => {
+                    match r[1..].find(q) {
+                        Some(e) => (&r[1..e+1], &r[e+2..]),
+                        None => return out,
+                    }
+                }
+                _ => {
+                    let e = r.find(char::is_whitespace).unwrap_or(r.len());
+                    (&r[..e], &r[e..])
+                }
+            };
+            value = decode_entities(v);
+            rest = after;
+        }
+        out.push((name, value));
+    }
+}
+
+/// Decode the character references in an attribute value, so we see
+/// the url the browser will see.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = rest[1..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '#'))
+            .map(|e| e + 1).unwrap_or(rest.len());
+        let entity = &rest[1..end];
+        let decoded = if let Some(n) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+            u32::from_str_radix(n, 16).ok().and_then(std::char::from_u32)
+        } else if let Some(n) = entity.strip_prefix('#') {
+            n.parse().ok().and_then(std::char::from_u32)
+        } else {
+            match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                "colon" => Some(':'),
+                "tab" => Some('\t'),
+                "newline" => Some('\n'),
+                _ => None,
+            }
+        };
+        if let Some(c) = decoded.filter(|_| end > 1) {
+            out.push(c);
+            rest = rest[end..].strip_prefix(';').unwrap_or(&rest[end..]);
+        } else {
+            out.push('&');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape_attribute(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[test]
+fn scripts_are_removed() {
+    for evil in &[
+        "<script>alert(1)</script>",
+        "<SCRIPT SRC=//evil.example/x.js></SCRIPT>",
+        "<script>if (a<b) alert(1)</script>",
+        "<img src=x onerror=alert(1)>",
+        "<img src=\"x\" onerror = 'alert(1)'>",
+        "<a href=\"javascript:alert(1)\">x</a>",
+        "<a href=\" JaVaScRiPt:alert(1)\">x</a>",
+        "<a href=\"java\tscript:alert(1)\">x</a>",
+        "<a href=\"&#106;avascript:alert(1)\">x</a>",
+        "<a href=\"javascript&colon;alert(1)\">x</a>",
+        "<a href=\"data:text/html,<script>alert(1)</script>\">x</a>",
+        "<iframe src=\"//evil.example\"></iframe>",
+        "<svg onload=alert(1)><script>alert(1)</script></svg>",
+        "<style>body{background:url(javascript:alert(1))}</style>",
+        "<div style=\"background:url(javascript:alert(1))\" onclick=\"alert(1)\">x</div>",
+        "<!-- <script>alert(1)</script> -->",
+        "<object data=\"x.swf\"></object><embed src=\"x.swf\">",
+        "<a href=x title='\"><script>alert(1)</script>'>x</a>",
+        "<scr<script>ipt>alert(1)</script>",
+        "<img src=x onerror=alert(1) ",
+        "<a href=\"&#x6A;&#97vascript:alert(1)\">x</a>",
+    ] {
+        let clean = sanitize_html(evil).to_lowercase();
+        for tag in crate::markdown::split_tags(&clean).into_iter().filter(|t| t.starts_with('<')) {
+            for bad in &["<script", "javascript:", " on", "data:", " style", "<style",
+                         "<iframe", "<object", "<embed", "<svg"] {
+                assert!(!tag.contains(bad), "{} gives {}", evil, clean);
+            }
+        }
+    }
+}
+
+#[test]
+fn descriptions_are_kept() {
+    for good in &[
+        r#"<p>If $a&lt;b$ then \[x_1 &gt; 0\].</p>"#,
+        r#"<p><a href="/concept/2" class="xref" data-ref="c2">A link</a></p>"#,
+        r#"<p><a href="http://physics.oregonstate.edu/portfolioswiki">wiki</a></p>"#,
+        r#"<p><img src="http://example.com/fig.png" alt="Figure 1" /><br /></p>"#,
+        r#"<table><thead><tr><th>x</th></tr></thead><tbody><tr><td colspan="2">1</td></tr></tbody></table>"#,
+        r#"<ul><li><strong>bold</strong> and <em>slanted</em></li></ul>"#,
+    ] {
+        assert_eq!(&sanitize_html(good), good);
+    }
+}
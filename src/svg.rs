@@ -0,0 +1,134 @@
+//! Drawing a layered graph as SVG, so the concept map can be shown
+//! as a picture without needing Graphviz.
+
+use std::collections::BTreeMap;
+use crate::layout::{Layout, Slot};
+
+/// The size of a node.
+const NODE_WIDTH: f64 = 160.0;
+const NODE_HEIGHT: f64 = 56.0;
+/// How far apart two nodes one apart in the layout are.
+const UNIT: f64 = 190.0;
+/// How far apart the tops of neighboring layers are.
+const LAYER_HEIGHT: f64 = 120.0;
+const MARGIN: f64 = 20.0;
+
+const STYLE: &str = "
+  rect { fill: #fff; stroke: #345; stroke-width: 1.5; }
+  a:hover rect { fill: #eef4ff; }
+  path.edge { fill: none; stroke: #678; stroke-width: 1.5; }
+  foreignObject div {
+    display: flex; align-items: center; justify-content: center;
+    width: 100%; height: 100%; box-sizing: border-box; padding: 2px 6px;
+    text-align: center; font-family: sans-serif; font-size: 13px; color: #123;
+  }
+  foreignObject img { height: 1.5em; margin-right: 0.25em; }
+";
+
+/// How to draw a node.
+pub struct Node {
+    /// The label, as html that is also valid xml.
+    pub label: String,
+    /// Where the node links to, as an html attribute value, quotes and
+    /// all, as `absolute_url` displays it.
+    pub href: String,
+    /// The color to fill the box with, if not the usual.
+    pub fill: Option<String>,
+}
+
+/// Draw a layout, with each node a box linking somewhere, and each
+/// edge a line from the bottom of its upper node through its dummies
+/// to the top of its lower node, with an arrow at the end it points
+/// to.
+pub fn render<N: Copy + Ord>(layout: &Layout<N>, node: impl Fn(N) -> Node) -> String {
+    let top = |l: usize| MARGIN + l as f64*LAYER_HEIGHT;
+    let left = |x: f64| MARGIN + x*UNIT;
+    let mut at: BTreeMap<(usize, Slot<N>), f64> = BTreeMap::new();
+    let mut layer_of: BTreeMap<N, usize> = BTreeMap::new();
+    for (l, layer) in layout.layers.iter().enumerate() {
+        for &(slot, x) in layer.iter() {
+            at.insert((l, slot), left(x) + 0.5*NODE_WIDTH);
+            if let Slot::Node(n) = slot {
+                layer_of.insert(n, l);
+            }
+        }
+    }
+    let width = layout.layers.iter().flatten().map(|&(_, x)| x).fold(0.0, f64::max);
+    let mut out = format!(
+        concat!(r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink""#,
+                r#" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}">"#, "\n",
+                "<style>{style}</style>\n",
+                r#"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5""#,
+                r#" markerWidth="8" markerHeight="8" orient="auto-start-reverse">"#,
+                r##"<path d="M0,0 L10,5 L0,10 z" fill="#678"/></marker></defs>"##, "\n"),
+        w = 2.0*MARGIN + width*UNIT + NODE_WIDTH,
+        h = 2.0*MARGIN + layout.layers.len().saturating_sub(1) as f64*LAYER_HEIGHT + NODE_HEIGHT,
+        style = STYLE);
+
+    for &(a, b) in layout.edges.iter() {
+        let (la, lb) = match (layer_of.get(&a), layer_of.get(&b)) {
+            (Some(&la), Some(&lb)) => (la, lb),
+            _ => continue,
+        };
+        // The line curves from layer to layer, and runs straight down
+        // through each dummy, which is as tall as a node.
+        let mut points = vec![(at[&(la, Slot::Node(a))], top(la) + NODE_HEIGHT)];
+        for l in la + 1..lb {
+            let x = at[&(l, Slot::Dummy(a, b))];
+            points.push((x, top(l)));
+            points.push((x, top(l) + NODE_HEIGHT));
+        }
+        points.push((at[&(lb, Slot::Node(b))], top(lb)));
+        let mut d = format!("M{:.1},{:.1}", points[0].0, points[0].1);
+        for (i, w) in points.windows(2).enumerate() {
+            let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+            if i % 2 == 0 {
+                let mid = 0.5*(y0 + y1);
+                d.push_str(&format!(" C{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}", x0, mid, x1, mid, x1, y1));
+            } else {
+                d.push_str(&format!(" L{:.1},{:.1}", x1, y1));
+            }
+        }
+        let marker = if layout.reversed.contains(&(b, a)) { "marker-start" } else { "marker-end" };
+        out.push_str(&format!("<path class=\"edge\" d=\"{}\" {}=\"url(#arrow)\"/>\n", d, marker));
+    }
+
+    for (l, layer) in layout.layers.iter().enumerate() {
+        for &(slot, x) in layer.iter() {
+            if let Slot::Node(n) = slot {
+                let Node { label, href, fill } = node(n);
+                let style = fill.map(|f| format!(r#" style="fill: {}""#, f)).unwrap_or_default();
+                out.push_str(&format!(
+                    concat!("<a href={href} xlink:href={href}>",
+                            r#"<rect x="{x:.1}" y="{y:.1}" width="{w}" height="{h}" rx="6"{style}/>"#,
+                            r#"<foreignObject x="{x:.1}" y="{y:.1}" width="{w}" height="{h}">"#,
+                            r#"<div xmlns="http://www.w3.org/1999/xhtml">{label}</div>"#,
+                            "</foreignObject></a>\n"),
+                    href = href, x = left(x), y = top(l), w = NODE_WIDTH, h = NODE_HEIGHT,
+                    style = style, label = label));
+            }
+        }
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+#[test]
+fn svg_routes_edges_through_dummies() {
+    let layout = crate::layout::layered(&[(1, 2), (2, 3), (1, 3), (4, 5), (5, 6), (6, 4)], 4, &[]);
+    let svg = render(&layout, |n| Node {
+        label: format!("node {}", n),
+        href: format!("\"/{}\"", n),
+        fill: None,
+    });
+    for n in 1..=6 {
+        assert!(svg.contains(&format!("<a href=\"/{}\"", n)));
+        assert!(svg.contains(&format!(">node {}</div>", n)));
+    }
+    let edges: Vec<&str> = svg.lines().filter(|l| l.starts_with("<path class=\"edge\"")).collect();
+    assert_eq!(edges.len(), 6);
+    // The edges from 1 to 3 and between 4 and 6 pass over a layer.
+    assert_eq!(edges.iter().filter(|e| e.contains(" L")).count(), 2);
+    // We drew one edge of the cycle backwards.
+    assert_eq!(edges.iter().filter(|e| e.contains("marker-start")).count(), 1);
+}
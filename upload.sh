@@ -14,8 +14,8 @@ wget -O libraries/jquery.js https://code.jquery.com/jquery-3.3.1.min.js
 
 fac
 
-# cargo run --release -- --base-url 'http://physics.oregonstate.edu/~roundyd/progression'
-cargo run --release -- --base-url 'https://paradigms.oregonstate.edu/progressions/derivatives'
+# cargo run --release --bin build-mirror -- --base-url 'http://physics.oregonstate.edu/~roundyd/progression'
+cargo run --release --bin build-mirror -- --base-url 'https://paradigms.oregonstate.edu/progressions/derivatives'
 
 SITEDIR=progressions/derivatives
 
@@ -24,8 +24,7 @@ ssh paradigms.oregonstate.edu "mkdir -p $SITEDIR"
 
 scp -r mirror/* paradigms.oregonstate.edu:$SITEDIR/
 
-scp style.css paradigms.oregonstate.edu:$SITEDIR/
-
-scp -r libraries paradigms.oregonstate.edu:$SITEDIR/
-
-scp -r figs paradigms.oregonstate.edu:$SITEDIR/
+# Or, with the site mounted locally (e.g. using sshfs), copy only
+# what changed, keeping what we need to roll back:
+# cargo run --release --bin deploy -- --target /mnt/paradigms/$SITEDIR
+# cargo run --release --bin deploy -- --target /mnt/paradigms/$SITEDIR --rollback